@@ -0,0 +1,90 @@
+//! Bars stored one by one against `TradeData` columns on a million bars.
+//!
+//! ```text
+//! cargo bench --bench layout
+//! ```
+
+use std::{ hint::black_box, time::{ Duration, Instant } };
+use chrono::{ DateTime, Utc };
+use tradecharts::chart::{ TradeInterval, tradedata::{ TradeItem, TradeData } };
+
+const BARS: usize = 1_000_000;
+const RUNS: u32 = 5;
+const SMA_LENGTH: usize = 20;
+
+fn bars() -> Vec<TradeItem> {
+    let start: i64 = 1_577_836_800;
+    (0..BARS)
+        .map(|i| {
+            let date: DateTime<Utc> = DateTime::from_timestamp(start + i as i64 * 60, 0).unwrap();
+            let o: f64 = 100.0 + (i % 1000) as f64 * 0.01;
+            TradeItem::new(date, o + 1.0, o - 1.0, o, o + 0.5, 10.0)
+        })
+        .collect()
+}
+
+/// Best time of `RUNS` runs of `f`.
+fn measure<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start: Instant = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn sma(closes: &[f64]) -> Vec<f64> {
+    closes.windows(SMA_LENGTH).map(|window| window.iter().sum::<f64>() / SMA_LENGTH as f64).collect()
+}
+
+fn report(name: &str, rows: Duration, columns: Duration) {
+    let ratio: f64 = rows.as_secs_f64() / columns.as_secs_f64().max(f64::EPSILON);
+    println!("{:<14} rows {:>10.3?}  columns {:>10.3?}  x{:.1}", name, rows, columns, ratio);
+}
+
+fn main() {
+    let items: Vec<TradeItem> = bars();
+    let mut trade_data: TradeData = TradeData::new(TradeInterval::Minute1);
+    items.iter().cloned().for_each(|item| trade_data.add_item(item));
+    println!("{} bars, best of {} runs", BARS, RUNS);
+
+    report(
+        "build",
+        measure(|| {
+            let mut rows: Vec<TradeItem> = Vec::new();
+            items.iter().cloned().for_each(|item| rows.push(item));
+            rows
+        }),
+        measure(|| {
+            let mut trade_data: TradeData = TradeData::new(TradeInterval::Minute1);
+            items.iter().cloned().for_each(|item| trade_data.add_item(item));
+            trade_data
+        }),
+    );
+    report(
+        "high/low scan",
+        measure(|| items.iter().fold((f64::MAX, f64::MIN), |(l, h), item| (l.min(item.hlocv().l), h.max(item.hlocv().h)))),
+        measure(|| {
+            let columns = trade_data.columns();
+            (columns.lows().iter().copied().fold(f64::MAX, f64::min), columns.highs().iter().copied().fold(f64::MIN, f64::max))
+        }),
+    );
+    report(
+        "close sum",
+        measure(|| items.iter().map(|item| item.hlocv().c).sum::<f64>()),
+        measure(|| trade_data.columns().closes().iter().sum::<f64>()),
+    );
+    // rows have to be copied into a column first, like for a WebGL upload
+    report(
+        "close sma",
+        measure(|| sma(&items.iter().map(|item| item.hlocv().c).collect::<Vec<f64>>())),
+        measure(|| sma(trade_data.columns().closes())),
+    );
+    report(
+        "date lookup",
+        measure(|| (0..1000).map(|i| items.partition_point(|item| item._timestamp() <= 1_577_836_800 + i * 60_000)).sum::<usize>()),
+        measure(|| (0..1000).filter_map(|i| trade_data.index_at(&DateTime::from_timestamp(1_577_836_800 + i * 60_000, 0).unwrap())).sum::<usize>()),
+    );
+}
@@ -0,0 +1,258 @@
+//! Command line tool fetching MOEX history, converting it between formats and summarizing it.
+//!
+//! ```text
+//! tradecharts fetch GAZP --interval day --from 2023-01-01 --out gazp.csv
+//! tradecharts convert gazp.csv gazp.json
+//! tradecharts stats gazp.json
+//! ```
+
+#[cfg(not(target_arch = "wasm32"))]
+mod cli {
+    use std::{ error::Error, fs, path::Path };
+    use chrono::{ DateTime, Duration, NaiveDate, Utc };
+    use tradecharts::cache::{ CachedChunk, CachedSource, FileStore };
+    use tradecharts::calendar;
+    use tradecharts::chart::{ Period, TradeInterval, tradedata::{ TradeItem, TradeData } };
+    use tradecharts::csv::{ self, CsvFormat };
+    use tradecharts::instrument::Instrument;
+    use tradecharts::moex::{ Board, Moex };
+    use tradecharts::source::DataSource;
+
+    pub const USAGE: &str = "\
+usage:
+  tradecharts fetch <TICKER> [--board ENGINE/MARKET/BOARD] [--interval INTERVAL] [--from YYYY-MM-DD] [--till YYYY-MM-DD]
+                             [--iss URL] [--cache DIR] [--out FILE] [--format FORMAT]
+  tradecharts convert <IN> <OUT> [--interval INTERVAL] [--format FORMAT]
+  tradecharts stats <FILE> [--interval INTERVAL]
+
+INTERVAL is minute1, minute10, hour, day (default), week or month.
+FORMAT is csv, json or cache, by default taken from the file extension: .csv and .txt are CSV, others JSON.
+Dates are on the Moscow exchange clock.";
+
+    type CliResult<T> = Result<T, Box<dyn Error>>;
+
+    /// File layouts: Finam-like CSV, `TradeData` as JSON and the chunks of the file cache.
+    #[derive(Debug)]
+    #[derive(Clone, Copy)]
+    #[derive(PartialEq)]
+    enum Format {
+        Csv,
+        Json,
+        Cache,
+    }
+
+    impl Format {
+        fn parse(name: &str) -> CliResult<Format> {
+            match name.to_lowercase().as_str() {
+                "csv"   => Ok(Format::Csv),
+                "json"  => Ok(Format::Json),
+                "cache" => Ok(Format::Cache),
+                _ => Err(format!("unknown format {}", name).into()),
+            }
+        }
+
+        fn of_path(path: &str) -> Format {
+            match Path::new(path).extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+                Some("csv") | Some("txt") => Format::Csv,
+                _ => Format::Json,
+            }
+        }
+    }
+
+    /// Arguments in the order given and the values of the `--name value` options.
+    struct Args {
+        positional: Vec<String>,
+        options: Vec<(String, String)>,
+    }
+
+    impl Args {
+        fn parse(args: &[String]) -> CliResult<Args> {
+            let mut positional: Vec<String> = Vec::new();
+            let mut options: Vec<(String, String)> = Vec::new();
+            let mut iter = args.iter();
+            while let Some(arg) = iter.next() {
+                match arg.strip_prefix("--") {
+                    Some(name) => {
+                        let value: &String = iter.next().ok_or_else(|| format!("option --{} needs a value", name))?;
+                        options.push((name.to_string(), value.clone()));
+                    },
+                    None => positional.push(arg.clone()),
+                }
+            }
+            Ok(Args { positional, options })
+        }
+
+        fn option(&self, name: &str) -> Option<&str> {
+            self.options.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+        }
+
+        fn positional(&self, idx: usize, what: &str) -> CliResult<&str> {
+            self.positional.get(idx).map(String::as_str).ok_or_else(|| format!("missing {}", what).into())
+        }
+
+        fn interval(&self) -> CliResult<TradeInterval> {
+            match self.option("interval").unwrap_or("day").to_lowercase().as_str() {
+                "minute1"   => Ok(TradeInterval::Minute1),
+                "minute10"  => Ok(TradeInterval::Minute10),
+                "hour"      => Ok(TradeInterval::Hour),
+                "day"       => Ok(TradeInterval::Day),
+                "week"      => Ok(TradeInterval::Week),
+                "month"     => Ok(TradeInterval::Month),
+                other => Err(format!("unknown interval {}", other).into()),
+            }
+        }
+
+        fn format(&self, path: &str) -> CliResult<Format> {
+            match self.option("format") {
+                Some(name) => Format::parse(name),
+                None => Ok(Format::of_path(path)),
+            }
+        }
+
+        fn date(&self, name: &str) -> CliResult<Option<NaiveDate>> {
+            match self.option(name) {
+                Some(date) => Ok(Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| format!("bad --{} {}: {}", name, date, e))?)),
+                None => Ok(None),
+            }
+        }
+
+        fn board(&self) -> CliResult<Board> {
+            match self.option("board") {
+                Some(path) => match path.split('/').collect::<Vec<&str>>().as_slice() {
+                    [engine, market, board] => Ok(Board::new(engine, market, board)),
+                    _ => Err(format!("board {} is not ENGINE/MARKET/BOARD", path).into()),
+                },
+                None => Ok(Board::default()),
+            }
+        }
+    }
+
+    /// Reads any of the formats, JSON may also be a plain array of bars like `TradeChart::export_json` writes.
+    fn read(path: &str, format: Format, interval: TradeInterval) -> CliResult<TradeData> {
+        let text: String = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        match format {
+            Format::Csv => Ok(csv::parse(&text, &CsvFormat::default(), interval)?),
+            Format::Json | Format::Cache => {
+                if let Ok(chunk) = serde_json::from_str::<CachedChunk>(&text) {
+                    chunk.data.validate()?;
+                    return Ok(chunk.data);
+                }
+                if let Ok(trade_data) = serde_json::from_str::<TradeData>(&text) {
+                    trade_data.validate()?;
+                    return Ok(trade_data);
+                }
+                let items: Vec<TradeItem> = serde_json::from_str(&text)?;
+                let mut trade_data: TradeData = TradeData::new(interval);
+                items.into_iter().for_each(|item| trade_data.add_item(item));
+                Ok(trade_data)
+            },
+        }
+    }
+
+    fn write(path: &str, format: Format, trade_data: &TradeData) -> CliResult<()> {
+        let text: String =
+            match format {
+                Format::Csv => csv::write(&trade_data.iter_data().collect::<Vec<TradeItem>>(), &CsvFormat::default()),
+                Format::Json => serde_json::to_string_pretty(trade_data)?,
+                Format::Cache => serde_json::to_string(&CachedChunk { covered: trade_data.period().clone(), data: trade_data.clone() })?,
+            };
+        fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path, e).into())
+    }
+
+    /// Period, bar count, price range and totals of the series.
+    pub fn stats(trade_data: &TradeData) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        if let Some(instrument) = trade_data.instrument() {
+            lines.push(format!("instrument: {}", instrument.key()));
+        }
+        lines.push(format!("interval: {:?}", trade_data._interval()));
+        let gaps: usize = trade_data.columns().gaps().iter().filter(|gap| **gap).count();
+        lines.push(format!("bars: {} ({} without trades)", trade_data.len(), gaps));
+        if let (Some(first), Some(last)) = (trade_data.first(), trade_data.last()) {
+            let date_format: &str = if trade_data._interval().is_intraday() { "%Y-%m-%d %H:%M" } else { "%Y-%m-%d" };
+            lines.push(format!("period: {} .. {} MSK", first.exchange_date().format(date_format), last.exchange_date().format(date_format)));
+            let range = trade_data.range();
+            lines.push(format!("range: {} .. {}", trade_data.format_price(range.start()), trade_data.format_price(range.end())));
+            let (open, close): (f64, f64) = (first.hlocv().o, last.hlocv().c);
+            let change: f64 = if open != 0.0 { (close / open - 1.0) * 100.0 } else { 0.0 };
+            lines.push(format!("open: {} close: {} change: {:+.2}%", trade_data.format_price(open), trade_data.format_price(close), change));
+            let volume: f64 = trade_data.columns().volumes().iter().sum();
+            lines.push(format!("volume: {:.0}", volume));
+        }
+        lines.join("\n")
+    }
+
+    async fn fetch(args: &Args) -> CliResult<TradeData> {
+        let instrument: Instrument = Instrument::with_board(args.positional(1, "ticker")?, args.board()?);
+        let interval: TradeInterval = args.interval()?;
+        let till: DateTime<Utc> =
+            match args.date("till")? {
+                Some(date) => calendar::exchange_midnight(date) + Duration::days(1) - Duration::seconds(1),
+                None => Utc::now(),
+            };
+        let from: DateTime<Utc> =
+            match args.date("from")? {
+                Some(date) => calendar::exchange_midnight(date),
+                None => till - interval.default_lookback(),
+            };
+
+        let moex: Moex =
+            match args.option("iss") {
+                Some(url) => Moex::with_base_url(url),
+                None => Moex::new(),
+            };
+        let source: Box<dyn DataSource> =
+            match args.option("cache") {
+                Some(dir) => Box::new(CachedSource::new(Box::new(moex), Some(Box::new(FileStore::new(dir))))),
+                None => Box::new(moex),
+            };
+        let mut trade_data: TradeData = source.fetch(&instrument, interval, &Period::new(from, till)).await?;
+        trade_data.set_instrument(instrument);
+        Ok(trade_data)
+    }
+
+    pub async fn run(args: &[String]) -> CliResult<String> {
+        let args: Args = Args::parse(args)?;
+        match args.positional(0, "command")? {
+            "fetch" => {
+                let trade_data: TradeData = fetch(&args).await?;
+                if let Some(out) = args.option("out") {
+                    write(out, args.format(out)?, &trade_data)?;
+                }
+                Ok(stats(&trade_data))
+            },
+            "convert" => {
+                let (input, output) = (args.positional(1, "input file")?, args.positional(2, "output file")?);
+                let trade_data: TradeData = read(input, Format::of_path(input), args.interval()?)?;
+                write(output, args.format(output)?, &trade_data)?;
+                Ok(format!("{} bars written to {}", trade_data.len(), output))
+            },
+            "stats" => {
+                let input: &str = args.positional(1, "file")?;
+                Ok(stats(&read(input, Format::of_path(input), args.interval()?)?))
+            },
+            other => Err(format!("unknown command {}", other).into()),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "--help" || args[0] == "help" {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("cannot start the runtime");
+    match runtime.block_on(cli::run(&args)) {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// The tool is native only, the browser uses the library through `TradeChart`.
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,539 @@
+use std::{ cell::RefCell, collections::VecDeque, error::Error, fmt, fs, future::Future, path::PathBuf, pin::Pin };
+use serde::{ Serialize, Deserialize };
+use chrono::{ Duration, Utc };
+use js_sys::Promise;
+use wasm_bindgen::{ prelude::*, JsCast };
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{ IdbDatabase, IdbOpenDbRequest, IdbRequest, IdbTransactionMode };
+use crate::instrument::Instrument;
+use crate::moex::MoexError;
+use crate::source::{ CallbackError, DataSource, SourceFuture, QuoteFuture };
+use crate::chart::{
+    Period, TradeInterval,
+    tradedata::{ MergeRule, TradeData },
+};
+
+const DEFAULT_MEMORY_CAPACITY: usize = 32;
+const DEFAULT_MAX_AGE_SECONDS: i64 = 300;
+const IDB_NAME: &str = "tradecharts";
+const IDB_STORE: &str = "history";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub symbol: String,
+    pub interval: TradeInterval,
+}
+
+impl CacheKey {
+    pub fn new(symbol: &str, interval: TradeInterval) -> CacheKey {
+        CacheKey { symbol: symbol.to_string(), interval }
+    }
+    /// Key of the chunk in a persistent store.
+    pub fn store_key(&self) -> String {
+        format!("{}:{:?}", self.symbol, self.interval)
+    }
+}
+
+/// Bars of an instrument together with the period they were requested for,
+/// the bars themselves may start later or end earlier (weekends, holidays).
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct CachedChunk {
+    pub covered: Period<Utc>,
+    pub data: TradeData,
+}
+
+impl CachedChunk {
+    /// The chunk answers for `period` if it covers it, allowing the end to be `max_age` stale.
+    pub fn answers(&self, period: &Period<Utc>, max_age: Duration) -> bool {
+        let covered: Period<Utc> = Period::new(*self.covered.begin(), *self.covered.end() + max_age);
+        covered.covers(period)
+    }
+
+    /// Parts of `period` the chunk does not cover: the head before it and the tail after it,
+    /// the whole period when they do not overlap. A part shares its bound with the chunk,
+    /// so a still forming last bar is fetched again.
+    pub fn missing(&self, period: &Period<Utc>) -> Vec<Period<Utc>> {
+        if !self.covered.overlaps(period) {
+            return vec![period.clone()];
+        }
+        let mut missing: Vec<Period<Utc>> = Vec::new();
+        if period.begin() < self.covered.begin() {
+            missing.push(Period::new(*period.begin(), *self.covered.begin()));
+        }
+        if period.end() > self.covered.end() {
+            missing.push(Period::new(*self.covered.end(), *period.end()));
+        }
+        missing
+    }
+
+    /// Adds freshly fetched bars, they replace the cached ones within `fetched.covered`.
+    /// A fetch apart from the chunk keeps the cached bars, but only its own period counts
+    /// as covered: the bars outside `covered` are fetched again when asked for.
+    pub fn extend(&mut self, fetched: CachedChunk) {
+        self.covered =
+            if self.covered.overlaps(&fetched.covered) {
+                Period::new(
+                    *self.covered.begin().min(fetched.covered.begin()),
+                    *self.covered.end().max(fetched.covered.end()),
+                )
+            } else {
+                fetched.covered.clone()
+            };
+        self.data.remove(&fetched.covered);
+        self.data.merge(fetched.data, MergeRule::Replace);
+    }
+}
+
+/// In-memory least recently used cache of chunks, the most recent entry is at the front.
+pub struct HistoryCache {
+    capacity: usize,
+    entries: VecDeque<(CacheKey, CachedChunk)>,
+}
+
+impl Default for HistoryCache {
+    fn default() -> Self {
+        HistoryCache::new(DEFAULT_MEMORY_CAPACITY)
+    }
+}
+
+impl HistoryCache {
+    pub fn new(capacity: usize) -> HistoryCache {
+        HistoryCache { capacity, entries: VecDeque::new() }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn get(&mut self, key: &CacheKey) -> Option<&CachedChunk> {
+        let idx: usize = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(idx)?;
+        self.entries.push_front(entry);
+        self.entries.front().map(|(_, chunk)| chunk)
+    }
+    pub fn put(&mut self, key: CacheKey, chunk: CachedChunk) {
+        self.entries.retain(|(k, _)| *k != key);
+        self.entries.push_front((key, chunk));
+        self.entries.truncate(self.capacity);
+    }
+}
+
+#[derive(Debug)]
+pub struct StoreError(String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cache store failed: {}", self.0)
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<JsValue> for StoreError {
+    fn from(e: JsValue) -> StoreError {
+        StoreError(e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> StoreError {
+        StoreError(e.to_string())
+    }
+}
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, StoreError>> + 'a>>;
+
+/// Persistent backend holding serialized chunks by key.
+pub trait CacheStore {
+    fn load<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>>;
+    fn save<'a>(&'a self, key: &'a str, value: String) -> StoreFuture<'a, ()>;
+}
+
+/// Native store keeping every chunk in its own JSON file under `dir`.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> FileStore {
+        FileStore { dir: dir.into() }
+    }
+    fn path(&self, key: &str) -> PathBuf {
+        let name: String = key.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+impl CacheStore for FileStore {
+    fn load<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            match fs::read_to_string(self.path(key)) {
+                Ok(text) => Ok(Some(text)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+    fn save<'a>(&'a self, key: &'a str, value: String) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            fs::create_dir_all(&self.dir)?;
+            Ok(fs::write(self.path(key), value)?)
+        })
+    }
+}
+
+/// Browser store keeping chunks in the `history` object store of the `tradecharts` IndexedDB database.
+pub struct IndexedDbStore {
+    db: IdbDatabase,
+}
+
+/// Promise settled by the success or error event of an IndexedDB request.
+fn request_promise(request: &IdbRequest) -> Promise {
+    let mut executor = |resolve: js_sys::Function, reject: js_sys::Function| {
+        let success_request: IdbRequest = request.clone();
+        let on_success = Closure::once_into_js(move || {
+            let _ = resolve.call1(&JsValue::NULL, &success_request.result().unwrap_or(JsValue::UNDEFINED));
+        });
+        let on_error = Closure::once_into_js(move || {
+            let _ = reject.call1(&JsValue::NULL, &JsValue::from_str("IndexedDB request failed"));
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    };
+    Promise::new(&mut executor)
+}
+
+impl IndexedDbStore {
+    pub async fn open() -> Result<IndexedDbStore, StoreError> {
+        let factory = web_sys::window()
+            .ok_or_else(|| StoreError(String::from("no window")))?
+            .indexed_db()?
+            .ok_or_else(|| StoreError(String::from("IndexedDB is not available")))?;
+        let request: IdbOpenDbRequest = factory.open_with_u32(IDB_NAME, 1)?;
+
+        let upgrade_request: IdbOpenDbRequest = request.clone();
+        let on_upgrade = Closure::once_into_js(move || {
+            if let Ok(db) = upgrade_request.result() {
+                let _ = db.unchecked_into::<IdbDatabase>().create_object_store(IDB_STORE);
+            }
+        });
+        request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+
+        let db: JsValue = JsFuture::from(request_promise(&request)).await?;
+        Ok(IndexedDbStore { db: db.unchecked_into() })
+    }
+}
+
+impl CacheStore for IndexedDbStore {
+    fn load<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let request: IdbRequest = self.db
+                .transaction_with_str(IDB_STORE)?
+                .object_store(IDB_STORE)?
+                .get(&JsValue::from_str(key))?;
+            Ok(JsFuture::from(request_promise(&request)).await?.as_string())
+        })
+    }
+    fn save<'a>(&'a self, key: &'a str, value: String) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let request: IdbRequest = self.db
+                .transaction_with_str_and_mode(IDB_STORE, IdbTransactionMode::Readwrite)?
+                .object_store(IDB_STORE)?
+                .put_with_key(&JsValue::from_str(&value), &JsValue::from_str(key))?;
+            JsFuture::from(request_promise(&request)).await?;
+            Ok(())
+        })
+    }
+}
+
+/// `DataSource` answering from the in-memory cache, then from the optional persistent
+/// store, and asking the wrapped source only for what neither of them covers.
+pub struct CachedSource {
+    source: Box<dyn DataSource>,
+    memory: RefCell<HistoryCache>,
+    store: Option<Box<dyn CacheStore>>,
+    max_age: Duration,
+}
+
+impl CachedSource {
+    pub fn new(source: Box<dyn DataSource>, store: Option<Box<dyn CacheStore>>) -> CachedSource {
+        CachedSource {
+            source,
+            memory: RefCell::new(HistoryCache::default()),
+            store,
+            max_age: Duration::seconds(DEFAULT_MAX_AGE_SECONDS),
+        }
+    }
+
+    /// How long the end of a cached chunk is considered up to date.
+    pub fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = max_age;
+    }
+
+    async fn stored(&self, key: &CacheKey) -> Option<CachedChunk> {
+        let text: String = self.store.as_ref()?.load(&key.store_key()).await.ok()??;
+        // a damaged chunk is fetched anew
+        serde_json::from_str(&text).ok().filter(|chunk: &CachedChunk| chunk.data.validate().is_ok())
+    }
+
+    async fn fetch_cached(&self, instrument: &Instrument, interval: TradeInterval, period: &Period<Utc>) -> Result<TradeData, Box<dyn Error>> {
+        let key: CacheKey = CacheKey::new(&instrument.key(), interval);
+
+        let hit: Option<TradeData> = self.memory.borrow_mut().get(&key)
+            .filter(|chunk| chunk.answers(period, self.max_age))
+            .map(|chunk| chunk.data.sliced(period));
+        if let Some(data) = hit {
+            return Ok(data);
+        }
+
+        let in_memory: Option<CachedChunk> = self.memory.borrow_mut().get(&key).cloned();
+        let cached: Option<CachedChunk> =
+            match in_memory {
+                Some(chunk) => Some(chunk),
+                None => self.stored(&key).await,
+            };
+        if let Some(chunk) = cached.as_ref().filter(|chunk| chunk.answers(period, self.max_age)) {
+            let data: TradeData = chunk.data.sliced(period);
+            self.memory.borrow_mut().put(key, chunk.clone());
+            return Ok(data);
+        }
+
+        let mut chunk: CachedChunk =
+            match cached {
+                Some(chunk) => chunk,
+                None => CachedChunk {
+                    covered: period.clone(),
+                    data: self.source.fetch(instrument, interval, period).await?,
+                },
+            };
+        // only what the chunk lacks is asked for, typically the bars since its end
+        for part in chunk.missing(period) {
+            let data: TradeData =
+                match self.source.fetch(instrument, interval, &part).await {
+                    Ok(data) => data,
+                    // no bars since the chunk ended, e.g. before the close or over a weekend
+                    Err(e) if is_no_data(e.as_ref()) => TradeData::new(interval),
+                    Err(e) => return Err(e),
+                };
+            let fetched: CachedChunk = CachedChunk { data, covered: part };
+            chunk.extend(fetched);
+        }
+        let data: TradeData = chunk.data.sliced(period);
+
+        if let Some(store) = self.store.as_ref() {
+            store.save(&key.store_key(), serde_json::to_string(&chunk)?).await?;
+        }
+        self.memory.borrow_mut().put(key, chunk);
+
+        Ok(data)
+    }
+}
+
+/// Whether the source failed only because the period has no bars, which a part of a chunk may lack.
+fn is_no_data(e: &(dyn Error + 'static)) -> bool {
+    matches!(e.downcast_ref::<MoexError>(), Some(MoexError::NoData(_)))
+        || matches!(e.downcast_ref::<CallbackError>(), Some(CallbackError::NoData(_)))
+}
+
+impl DataSource for CachedSource {
+    fn fetch<'a>(&'a self, instrument: &'a Instrument, interval: TradeInterval, period: &'a Period<Utc>) -> SourceFuture<'a> {
+        Box::pin(self.fetch_cached(instrument, interval, period))
+    }
+    fn quote<'a>(&'a self, instrument: &'a Instrument) -> QuoteFuture<'a> {
+        self.source.quote(instrument)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ cell::Cell, rc::Rc };
+    use chrono::{ DateTime, NaiveDate, TimeZone };
+    use crate::chart::tradedata::TradeItem;
+    use crate::calendar;
+    use crate::moex::{ Board, Moex };
+    use crate::stand_in::{ StandIn, query_param };
+
+    /// Daily bars for every requested day, counting the calls.
+    struct CountingSource {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl DataSource for CountingSource {
+        fn fetch<'a>(&'a self, _instrument: &'a Instrument, interval: TradeInterval, period: &'a Period<Utc>) -> SourceFuture<'a> {
+            self.calls.set(self.calls.get() + 1);
+            Box::pin(async move {
+                let mut trade_data: TradeData = TradeData::new(interval);
+                let mut d: DateTime<Utc> = *period.begin();
+                while d <= *period.end() {
+                    trade_data.add_item(TradeItem::new(d, 2.0, 1.0, 1.5, 1.5, 10.0));
+                    d += Duration::days(1);
+                }
+                Ok(trade_data)
+            })
+        }
+    }
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 3, d, 0, 0, 0).unwrap()
+    }
+
+    fn chunk(b: u32, e: u32) -> CachedChunk {
+        CachedChunk { covered: Period::new(day(b), day(e)), data: TradeData::new(TradeInterval::Day) }
+    }
+
+    #[test]
+    fn memory_is_lru() {
+        let mut cache = HistoryCache::new(2);
+        cache.put(CacheKey::new("GAZP", TradeInterval::Day), chunk(1, 2));
+        cache.put(CacheKey::new("SBER", TradeInterval::Day), chunk(1, 2));
+        assert!(cache.get(&CacheKey::new("GAZP", TradeInterval::Day)).is_some());
+        cache.put(CacheKey::new("LKOH", TradeInterval::Day), chunk(1, 2));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&CacheKey::new("SBER", TradeInterval::Day)).is_none());
+        assert!(cache.get(&CacheKey::new("GAZP", TradeInterval::Day)).is_some());
+        assert!(cache.get(&CacheKey::new("GAZP", TradeInterval::Hour)).is_none());
+    }
+
+    #[test]
+    fn chunk_answers_within_max_age() {
+        let cached = chunk(1, 10);
+        assert!(cached.answers(&Period::new(day(2), day(10)), Duration::zero()));
+        assert!(!cached.answers(&Period::new(day(2), day(11)), Duration::zero()));
+        assert!(cached.answers(&Period::new(day(2), day(11)), Duration::days(1)));
+        assert!(!cached.answers(&Period::new(day(1) - Duration::days(1), day(5)), Duration::days(1)));
+    }
+
+    fn filled(b: u32, e: u32, close: f64) -> CachedChunk {
+        let mut cached: CachedChunk = chunk(b, e);
+        (b..=e).for_each(|d| cached.data.add_item(TradeItem::new(day(d), close, close, close, close, 10.0)));
+        cached
+    }
+
+    #[test]
+    fn chunk_extends_by_what_it_misses() {
+        let mut cached = filled(5, 10, 1.0);
+        assert_eq!(cached.missing(&Period::new(day(6), day(9))), Vec::<Period<Utc>>::new());
+        assert_eq!(cached.missing(&Period::new(day(1), day(20))), vec![Period::new(day(1), day(5)), Period::new(day(10), day(20))]);
+        assert_eq!(cached.missing(&Period::new(day(12), day(20))), vec![Period::new(day(12), day(20))]);
+
+        // the fetched bars replace the cached ones from the old end on
+        cached.extend(filled(10, 12, 2.0));
+        assert_eq!(cached.covered, Period::new(day(5), day(12)));
+        let closes: Vec<f64> = cached.data.iter_data().map(|item| item.hlocv().c).collect();
+        assert_eq!(closes, vec![1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0]);
+
+        // a fetch apart from the chunk keeps its bars, only the new period counts as covered
+        cached.extend(filled(20, 21, 3.0));
+        assert_eq!(cached.covered, Period::new(day(20), day(21)));
+        assert_eq!(cached.data.len(), 10);
+        assert!(cached.data.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn cached_source_fetches_only_the_tail() {
+        // daily history bars for every day from `from` to `till`
+        let server = StandIn::serve(|target| {
+            let date = |name: &str| NaiveDate::parse_from_str(query_param(target, name).unwrap_or_default(), "%Y-%m-%d").unwrap();
+            let rows: Vec<String> = date("from").iter_days()
+                .take_while(|d| *d <= date("till"))
+                .map(|d| format!(r#"["{}", 2, 1, 1.5, 1.5, 10]"#, d))
+                .collect();
+            (200, format!(r#"{{"history": {{"columns": ["TRADEDATE", "HIGH", "LOW", "OPEN", "CLOSE", "VOLUME"], "data": [{}]}}}}"#, rows.join(",")))
+        });
+        let mut source = CachedSource::new(Box::new(Moex::with_base_url(server.url())), None);
+        source.set_max_age(Duration::zero());
+        let gazp = Instrument::with_board("GAZP", Board::shares());
+        // daily bars are dated at midnight on the exchange clock
+        let days = |b: u32, e: u32| Period::new(
+            calendar::exchange_midnight(day(b).date_naive()),
+            calendar::exchange_midnight(day(e).date_naive()),
+        );
+
+        let data = source.fetch(&gazp, TradeInterval::Day, &days(1, 10)).await.unwrap();
+        assert_eq!(data.len(), 10);
+        let data = source.fetch(&gazp, TradeInterval::Day, &days(3, 15)).await.unwrap();
+        assert_eq!(data.len(), 13);
+        assert_eq!(*data.period(), days(3, 15));
+
+        let requests: Vec<String> = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(query_param(&requests[1], "from"), Some("2023-03-10"));
+        assert_eq!(query_param(&requests[1], "till"), Some("2023-03-15"));
+    }
+
+    #[tokio::test]
+    async fn cached_source_takes_an_empty_tail() {
+        // daily history bars up to 2023-03-09, nothing after it
+        let last: NaiveDate = NaiveDate::from_ymd_opt(2023, 3, 9).unwrap();
+        let server = StandIn::serve(move |target| {
+            let date = |name: &str| NaiveDate::parse_from_str(query_param(target, name).unwrap_or_default(), "%Y-%m-%d").unwrap();
+            let rows: Vec<String> = date("from").iter_days()
+                .take_while(|d| *d <= date("till") && *d <= last)
+                .map(|d| format!(r#"["{}", 2, 1, 1.5, 1.5, 10]"#, d))
+                .collect();
+            (200, format!(r#"{{"history": {{"columns": ["TRADEDATE", "HIGH", "LOW", "OPEN", "CLOSE", "VOLUME"], "data": [{}]}}}}"#, rows.join(",")))
+        });
+        let mut source = CachedSource::new(Box::new(Moex::with_base_url(server.url())), None);
+        source.set_max_age(Duration::zero());
+        let gazp = Instrument::with_board("GAZP", Board::shares());
+        let days = |b: u32, e: u32| Period::new(
+            calendar::exchange_midnight(day(b).date_naive()),
+            calendar::exchange_midnight(day(e).date_naive()),
+        );
+
+        let data = source.fetch(&gazp, TradeInterval::Day, &days(1, 10)).await.unwrap();
+        assert_eq!(data.len(), 9);
+        // the tail has no bars yet, the cached ones are still given
+        let data = source.fetch(&gazp, TradeInterval::Day, &days(3, 15)).await.unwrap();
+        assert_eq!(data.len(), 7);
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(query_param(&server.requests()[1], "from"), Some("2023-03-10"));
+
+        // and the empty tail counts as covered
+        let key: CacheKey = CacheKey::new(&gazp.key(), TradeInterval::Day);
+        assert_eq!(source.memory.borrow_mut().get(&key).map(|chunk| chunk.covered.clone()), Some(days(1, 15)));
+    }
+
+    #[tokio::test]
+    async fn cached_source_uses_memory_and_store() {
+        let dir = std::env::temp_dir().join(format!("tradecharts-cache-{}", std::process::id()));
+        let calls: Rc<Cell<usize>> = Rc::new(Cell::new(0));
+        let cached = |calls: &Rc<Cell<usize>>| CachedSource::new(
+            Box::new(CountingSource { calls: calls.clone() }),
+            Some(Box::new(FileStore::new(&dir))),
+        );
+        let gazp = Instrument::with_board("GAZP", Board::shares());
+
+        let source = cached(&calls);
+        let data = source.fetch(&gazp, TradeInterval::Day, &Period::new(day(1), day(10))).await.unwrap();
+        assert_eq!(data.len(), 10);
+        let data = source.fetch(&gazp, TradeInterval::Day, &Period::new(day(3), day(5))).await.unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(calls.get(), 1);
+
+        // a new instance starts with empty memory and reads the chunk back from the files
+        let source = cached(&calls);
+        let data = source.fetch(&gazp, TradeInterval::Day, &Period::new(day(1), day(10))).await.unwrap();
+        assert_eq!(data.len(), 10);
+        assert_eq!(calls.get(), 1);
+
+        // a longer period goes to the source and extends the chunk
+        let data = source.fetch(&gazp, TradeInterval::Day, &Period::new(day(5), day(20))).await.unwrap();
+        assert_eq!(data.len(), 16);
+        assert_eq!(calls.get(), 2);
+        let data = source.fetch(&gazp, TradeInterval::Day, &Period::new(day(1), day(20))).await.unwrap();
+        assert_eq!(data.len(), 20);
+        assert_eq!(calls.get(), 2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashSet;
+use chrono::{ DateTime, Datelike, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday };
+use crate::chart::{ Period, TradeInterval };
+
+/// Moscow standard time, UTC+3.
+const MOSCOW_OFFSET_SECONDS: i32 = 3 * 3600;
+/// Moscow summer time, UTC+4, kept all year round from 2011-03-27 to 2014-10-26.
+const MOSCOW_SUMMER_OFFSET_SECONDS: i32 = 4 * 3600;
+
+/// Moscow time with its history: UTC+3 with summer time at UTC+4 from the last Sunday of March
+/// to the last Sunday of October (of September until 1995), UTC+4 all year round from 2011-03-27
+/// and UTC+3 again since 2014-10-26. The rules hold from 1992 on, when Moscow came back to UTC+3;
+/// earlier moments, before any MOEX history, are taken by the rules of 1992.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub struct Moscow;
+
+impl Moscow {
+    /// Offset in effect at the moment `utc`.
+    fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+        let at = |y: i32, m: u32, d: u32, h: u32| NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, 0, 0).unwrap();
+        let summer: bool =
+            if *utc >= at(2014, 10, 25, 22) {
+                false
+            } else if *utc >= at(2011, 3, 26, 23) {
+                true
+            } else {
+                let year: i32 = utc.year();
+                let end_month: u32 = if year < 1996 { 9 } else { 10 };
+                // 02:00 standard time to 03:00 summer time, both 23:00 UTC on the Saturday before
+                let begin: NaiveDateTime = last_sunday(year, 3).and_hms_opt(2, 0, 0).unwrap() - Duration::seconds(MOSCOW_OFFSET_SECONDS as i64);
+                let end: NaiveDateTime = last_sunday(year, end_month).and_hms_opt(3, 0, 0).unwrap() - Duration::seconds(MOSCOW_SUMMER_OFFSET_SECONDS as i64);
+                begin <= *utc && *utc < end
+            };
+        let seconds: i32 = if summer { MOSCOW_SUMMER_OFFSET_SECONDS } else { MOSCOW_OFFSET_SECONDS };
+        FixedOffset::east_opt(seconds).unwrap()
+    }
+}
+
+fn last_sunday(year: i32, month: u32) -> NaiveDate {
+    let last: NaiveDate = NaiveDate::from_ymd_opt(year, month + 1, 1).and_then(|d| d.pred_opt()).unwrap();
+    last - Duration::days(last.weekday().num_days_from_sunday() as i64)
+}
+
+impl TimeZone for Moscow {
+    type Offset = FixedOffset;
+
+    fn from_offset(_offset: &FixedOffset) -> Moscow {
+        Moscow
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+        self.offset_from_local_datetime(&local.and_time(NaiveTime::default()))
+    }
+
+    /// The clock set back in autumn reads an hour twice, the summer time one first;
+    /// the clock set forward in spring skips an hour.
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+        let fits: Vec<FixedOffset> = [MOSCOW_SUMMER_OFFSET_SECONDS, MOSCOW_OFFSET_SECONDS].iter()
+            .map(|seconds| FixedOffset::east_opt(*seconds).unwrap())
+            .filter(|offset| Moscow::offset_at(&(*local - Duration::seconds(offset.local_minus_utc() as i64))) == *offset)
+            .collect();
+        match fits.as_slice() {
+            [offset] => LocalResult::Single(*offset),
+            [summer, standard] => LocalResult::Ambiguous(*summer, *standard),
+            _ => LocalResult::None,
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+        Moscow::offset_at(&utc.and_time(NaiveTime::default()))
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+        Moscow::offset_at(utc)
+    }
+}
+
+/// Time zone of the exchange, MOEX reports and trades in Moscow time.
+pub fn exchange_tz() -> Moscow {
+    Moscow
+}
+
+/// Moment of a date and time read on the exchange clock, like the ones in ISS responses.
+/// An hour read twice is taken the first time, an hour skipped by the clock at standard time.
+pub fn exchange_datetime(naive: NaiveDateTime) -> DateTime<Utc> {
+    match Moscow.from_local_datetime(&naive).earliest() {
+        Some(d) => d.with_timezone(&Utc),
+        None => (naive - Duration::seconds(MOSCOW_OFFSET_SECONDS as i64)).and_utc(),
+    }
+}
+
+/// Date and time on the exchange clock.
+pub fn exchange_local(d: &DateTime<Utc>) -> NaiveDateTime {
+    d.with_timezone(&exchange_tz()).naive_local()
+}
+
+/// Start of the exchange day `date`.
+pub fn exchange_midnight(date: NaiveDate) -> DateTime<Utc> {
+    exchange_datetime(date.and_time(NaiveTime::default()))
+}
+
+/// Trading hours within a day, on the exchange clock.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub struct Session {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl Session {
+    pub fn new(open: NaiveTime, close: NaiveTime) -> Session {
+        Session { open, close }
+    }
+}
+
+/// Trading days and hours of the exchange: weekends and holidays are closed,
+/// single weekend days may be made trading days by the exchange.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct TradingCalendar {
+    sessions: Vec<Session>,
+    /// Holidays repeating every year, as month and day.
+    yearly_holidays: Vec<(u32, u32)>,
+    holidays: HashSet<NaiveDate>,
+    working_weekends: HashSet<NaiveDate>,
+}
+
+impl Default for TradingCalendar {
+    fn default() -> Self {
+        TradingCalendar::moex()
+    }
+}
+
+impl TradingCalendar {
+    /// Calendar without sessions and holidays, to be filled in.
+    pub fn new() -> TradingCalendar {
+        TradingCalendar {
+            sessions: Vec::new(),
+            yearly_holidays: Vec::new(),
+            holidays: HashSet::new(),
+            working_weekends: HashSet::new(),
+        }
+    }
+
+    /// MOEX stock market: the main session and the evening one, closed on the public holidays.
+    /// Holidays moved by the government decree of the year are to be added with `add_holiday`.
+    pub fn moex() -> TradingCalendar {
+        let mut calendar: TradingCalendar = TradingCalendar::new();
+        calendar.sessions = moex_sessions("stock", "shares");
+        calendar.yearly_holidays = vec![(1, 1), (1, 2), (1, 7), (2, 23), (3, 8), (5, 1), (5, 9), (6, 12), (11, 4), (12, 31)];
+        calendar
+    }
+
+    /// The same days with the sessions of the MOEX market, `engine` and `market` as in ISS:
+    /// bonds have no evening session, currencies trade through the evening without a break
+    /// and futures stop for the clearings.
+    pub fn for_market(&self, engine: &str, market: &str) -> TradingCalendar {
+        TradingCalendar { sessions: moex_sessions(engine, market), ..self.clone() }
+    }
+
+    pub fn add_session(&mut self, session: Session) {
+        self.sessions.push(session);
+    }
+
+    pub fn add_holiday(&mut self, date: NaiveDate) {
+        self.working_weekends.remove(&date);
+        self.holidays.insert(date);
+    }
+
+    /// Saturday or Sunday the exchange trades on.
+    pub fn add_working_day(&mut self, date: NaiveDate) {
+        self.holidays.remove(&date);
+        self.working_weekends.insert(date);
+    }
+
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        if self.working_weekends.contains(&date) {
+            return true;
+        }
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+            && !self.holidays.contains(&date)
+            && !self.yearly_holidays.contains(&(date.month(), date.day()))
+    }
+
+    /// Whether a session is going on at `d`.
+    pub fn is_open(&self, d: &DateTime<Utc>) -> bool {
+        let local: NaiveDateTime = exchange_local(d);
+        self.is_trading_day(local.date())
+            && self.sessions.iter().any(|s| s.open <= local.time() && local.time() < s.close)
+    }
+
+    /// Last trading day before `date`.
+    pub fn previous_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut day: NaiveDate = date.pred_opt().unwrap_or(date);
+        while !self.is_trading_day(day) {
+            day = match day.pred_opt() { Some(day) => day, None => break };
+        }
+        day
+    }
+
+    /// Trading days on the exchange clock within `period`.
+    pub fn trading_days(&self, period: &Period<Utc>) -> Vec<NaiveDate> {
+        let last: NaiveDate = exchange_local(period.end()).date();
+        exchange_local(period.begin()).date()
+            .iter_days()
+            .take_while(|date| *date <= last)
+            .filter(|date| self.is_trading_day(*date))
+            .collect()
+    }
+
+    /// Starts of the bars of `interval` an exchange with this calendar has within `period`:
+    /// intraday bars within the sessions of the trading days, longer ones containing a trading day.
+    pub fn bar_starts(&self, interval: TradeInterval, period: &Period<Utc>) -> Vec<DateTime<Utc>> {
+        let mut starts: Vec<DateTime<Utc>> = Vec::new();
+        for date in self.trading_days(period) {
+            match interval {
+                _ if interval.is_intraday() => {
+                    let step: Duration = Duration::seconds(interval._seconds() as i64);
+                    for session in self.sessions.iter() {
+                        let close: DateTime<Utc> = exchange_datetime(date.and_time(session.close));
+                        let mut start: DateTime<Utc> = interval.bar_start(exchange_datetime(date.and_time(session.open)));
+                        while start < close {
+                            starts.push(start);
+                            start += step;
+                        }
+                    }
+                },
+                _ => starts.push(interval.bar_start(exchange_midnight(date))),
+            }
+        }
+        starts.retain(|start| period.contains(start));
+        starts.dedup();
+        starts
+    }
+}
+
+/// Sessions of a MOEX market, those of shares for the markets not told apart.
+fn moex_sessions(engine: &str, market: &str) -> Vec<Session> {
+    let session = |h: u32, m: u32, close_h: u32, close_m: u32| Session::new(
+        NaiveTime::from_hms_opt(h, m, 0).unwrap(),
+        NaiveTime::from_hms_opt(close_h, close_m, 0).unwrap(),
+    );
+    match (engine, market) {
+        ("stock", "bonds") => vec![session(10, 0, 18, 40)],
+        ("currency", _) => vec![session(10, 0, 23, 50)],
+        ("futures", _) => vec![session(10, 0, 14, 0), session(14, 5, 18, 50), session(19, 5, 23, 50)],
+        _ => vec![session(10, 0, 18, 50), session(19, 0, 23, 50)],
+    }
+}
+
+/// Label of the time axis at a bar starting at `d`, on the exchange clock.
+pub fn time_label(d: &DateTime<Utc>, interval: TradeInterval) -> String {
+    let format: &str =
+        match interval {
+            TradeInterval::Month => "%m.%Y",
+            _ if interval.is_intraday() => "%H:%M",
+            _ => "%d.%m.%y",
+        };
+    exchange_local(d).format(format).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::chart::tradedata::{ TradeItem, TradeData };
+
+    fn msk(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        exchange_tz().with_ymd_and_hms(y, m, d, h, min, 0).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn exchange_clock() {
+        let naive: NaiveDateTime = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        assert_eq!(exchange_datetime(naive), Utc.with_ymd_and_hms(2023, 3, 1, 7, 0, 0).unwrap());
+        assert_eq!(exchange_local(&exchange_datetime(naive)), naive);
+        assert_eq!(exchange_midnight(naive.date()), Utc.with_ymd_and_hms(2023, 2, 28, 21, 0, 0).unwrap());
+        assert_eq!(time_label(&msk(2023, 3, 1, 0, 0), TradeInterval::Day), "01.03.23");
+        assert_eq!(time_label(&msk(2023, 3, 1, 19, 10), TradeInterval::Minute10), "19:10");
+    }
+
+    #[test]
+    fn moscow_offsets_in_history() {
+        let opening = |y: i32, m: u32, d: u32| exchange_datetime(NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(10, 0, 0).unwrap());
+        // UTC+4 all year round from 2011-03-27 to 2014-10-26
+        assert_eq!(opening(2012, 1, 10), Utc.with_ymd_and_hms(2012, 1, 10, 6, 0, 0).unwrap());
+        assert_eq!(opening(2014, 10, 24), Utc.with_ymd_and_hms(2014, 10, 24, 6, 0, 0).unwrap());
+        assert_eq!(opening(2014, 10, 27), Utc.with_ymd_and_hms(2014, 10, 27, 7, 0, 0).unwrap());
+        // summer time before, to the last Sunday of October and of September until 1995
+        assert_eq!(opening(2010, 1, 15), Utc.with_ymd_and_hms(2010, 1, 15, 7, 0, 0).unwrap());
+        assert_eq!(opening(2010, 7, 1), Utc.with_ymd_and_hms(2010, 7, 1, 6, 0, 0).unwrap());
+        assert_eq!(opening(2010, 10, 29), Utc.with_ymd_and_hms(2010, 10, 29, 6, 0, 0).unwrap());
+        assert_eq!(opening(2010, 11, 1), Utc.with_ymd_and_hms(2010, 11, 1, 7, 0, 0).unwrap());
+        assert_eq!(opening(1995, 10, 2), Utc.with_ymd_and_hms(1995, 10, 2, 7, 0, 0).unwrap());
+        assert_eq!(opening(2000, 10, 2), Utc.with_ymd_and_hms(2000, 10, 2, 6, 0, 0).unwrap());
+
+        // the switches happen at 23:00 UTC on Saturday, and at 22:00 UTC for the last one
+        assert_eq!(exchange_local(&Utc.with_ymd_and_hms(2010, 3, 27, 22, 59, 0).unwrap()).to_string(), "2010-03-28 01:59:00");
+        assert_eq!(exchange_local(&Utc.with_ymd_and_hms(2010, 3, 27, 23, 0, 0).unwrap()).to_string(), "2010-03-28 03:00:00");
+        assert_eq!(exchange_local(&Utc.with_ymd_and_hms(2014, 10, 25, 21, 59, 0).unwrap()).to_string(), "2014-10-26 01:59:00");
+        assert_eq!(exchange_local(&Utc.with_ymd_and_hms(2014, 10, 25, 22, 0, 0).unwrap()).to_string(), "2014-10-26 01:00:00");
+        // an hour read twice is the summer time one, a skipped hour is read at standard time
+        let naive = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(exchange_datetime(naive("2010-10-31 02:30")), Utc.with_ymd_and_hms(2010, 10, 30, 22, 30, 0).unwrap());
+        assert_eq!(exchange_datetime(naive("2010-03-28 02:30")), Utc.with_ymd_and_hms(2010, 3, 27, 23, 30, 0).unwrap());
+        // before 1992 by the rules of 1992
+        assert_eq!(opening(1990, 7, 2), Utc.with_ymd_and_hms(1990, 7, 2, 6, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn trading_days_and_sessions() {
+        let mut calendar = TradingCalendar::moex();
+        // Wednesday 8 March is a holiday, Saturday 11 March made a trading day for the example
+        calendar.add_working_day(NaiveDate::from_ymd_opt(2023, 3, 11).unwrap());
+        let days = calendar.trading_days(&Period::new(msk(2023, 3, 6, 0, 0), msk(2023, 3, 12, 23, 0)));
+        let days: Vec<u32> = days.iter().map(|d| d.day()).collect();
+        assert_eq!(days, [6, 7, 9, 10, 11]);
+
+        assert!(calendar.is_open(&msk(2023, 3, 6, 10, 0)));
+        assert!(!calendar.is_open(&msk(2023, 3, 6, 18, 55)));
+        assert!(calendar.is_open(&msk(2023, 3, 6, 23, 0)));
+        assert!(!calendar.is_open(&msk(2023, 3, 8, 12, 0)));
+
+        let hours = calendar.bar_starts(TradeInterval::Hour, &Period::new(msk(2023, 3, 6, 0, 0), msk(2023, 3, 6, 23, 59)));
+        // 10:00 to 18:00 in the main session, 19:00 to 23:00 in the evening one
+        assert_eq!(hours.len(), 9 + 5);
+        assert_eq!(hours[0], msk(2023, 3, 6, 10, 0));
+        assert_eq!(hours[9], msk(2023, 3, 6, 19, 0));
+
+        let weeks = calendar.bar_starts(TradeInterval::Week, &Period::new(msk(2023, 3, 6, 0, 0), msk(2023, 3, 19, 0, 0)));
+        assert_eq!(weeks, [msk(2023, 3, 6, 0, 0), msk(2023, 3, 13, 0, 0)]);
+
+        // the other markets keep the days and have sessions of their own
+        let monday = Period::new(msk(2023, 3, 6, 0, 0), msk(2023, 3, 6, 23, 59));
+        let futures = calendar.for_market("futures", "forts");
+        assert!(!futures.is_open(&msk(2023, 3, 6, 14, 2)));
+        assert_eq!(futures.bar_starts(TradeInterval::Hour, &monday).len(), 14);
+        assert!(!futures.is_trading_day(NaiveDate::from_ymd_opt(2023, 3, 8).unwrap()));
+        assert!(futures.is_trading_day(NaiveDate::from_ymd_opt(2023, 3, 11).unwrap()));
+        let currency = calendar.for_market("currency", "selt");
+        assert!(currency.is_open(&msk(2023, 3, 6, 18, 55)));
+        assert_eq!(currency.bar_starts(TradeInterval::Hour, &monday).len(), 14);
+        let bonds = calendar.for_market("stock", "bonds");
+        assert!(!bonds.is_open(&msk(2023, 3, 6, 19, 30)));
+        assert_eq!(bonds.bar_starts(TradeInterval::Hour, &monday).len(), 9);
+    }
+
+    #[test]
+    fn missing_sessions() {
+        let calendar = TradingCalendar::moex();
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Hour);
+        for (d, h) in [(6, 10), (6, 11), (9, 10), (10, 15)] {
+            trade_data.add_item(TradeItem::new(msk(2023, 3, d, h, 0), 2.0, 1.0, 1.5, 1.5, 10.0));
+        }
+        // the 7th is a trading day without bars, the 8th a holiday
+        assert_eq!(trade_data.missing_sessions(&calendar), [NaiveDate::from_ymd_opt(2023, 3, 7).unwrap()]);
+
+        // the bars missing from the session grid become gaps, the nights and the holiday stay out
+        trade_data.fill_sessions(&calendar);
+        let gaps: Vec<DateTime<Utc>> = trade_data.iter_data().filter(|item| item.is_gap()).map(|item| *item.date()).collect();
+        assert_eq!(gaps.len(), (14 - 2) + 14 + (14 - 1) + 5);
+        assert!(gaps.iter().all(|d| calendar.is_open(d)));
+        assert_eq!(gaps[0], msk(2023, 3, 6, 12, 0));
+        assert_eq!(trade_data.len(), 4 + gaps.len());
+        // gaps keep the close of the bar before
+        assert_eq!(trade_data.item(2).unwrap().hlocv().c, 1.5);
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{ Serialize, Deserialize };
+use wasm_bindgen::prelude::*;
+use crate::http::percent_encode;
+use crate::moex::{ Board, Moex, MoexConfig, MoexError, MoexHistory, MoexValue, get_string, get_value };
+
+#[derive(Debug)]
+#[derive(Serialize,Deserialize)]
+pub struct MoexSecuritiesResponse {
+    pub securities: MoexHistory,
+}
+
+/// Security found by the ISS search.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CatalogEntry {
+    pub secid: String,
+    pub short_name: String,
+    pub name: String,
+    pub is_traded: bool,
+    /// ISS group like `stock_shares`, the engine and market joined by `_`.
+    pub group: String,
+    pub primary_board: String,
+}
+
+impl CatalogEntry {
+    /// Board the security is primarily traded on.
+    pub fn board(&self) -> Option<Board> {
+        let (engine, market) = self.group.split_once('_')?;
+        if self.primary_board.is_empty() {
+            return None;
+        }
+        Some(Board::new(engine, market, &self.primary_board))
+    }
+}
+
+#[wasm_bindgen]
+impl CatalogEntry {
+    /// Engine of the primary board, empty when the security has none.
+    #[wasm_bindgen(getter)]
+    pub fn engine(&self) -> String {
+        self.board().map(|board| board.engine).unwrap_or_default()
+    }
+    /// Market of the primary board, empty when the security has none.
+    #[wasm_bindgen(getter)]
+    pub fn market(&self) -> String {
+        self.board().map(|board| board.market).unwrap_or_default()
+    }
+}
+
+/// Trading parameters of a security on a board.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct SecurityDescription {
+    pub secid: String,
+    #[wasm_bindgen(skip)]
+    pub board: Board,
+    pub short_name: String,
+    pub lot_size: u32,
+    /// Number of decimals prices are quoted with.
+    pub decimals: u32,
+    /// Minimal price step.
+    pub min_step: f64,
+    pub currency: Option<String>,
+}
+
+#[wasm_bindgen]
+impl SecurityDescription {
+    #[wasm_bindgen(getter)]
+    pub fn board_id(&self) -> String {
+        self.board.board.clone()
+    }
+}
+
+fn text(table: &MoexHistory, row: &[MoexValue], name: &str) -> Option<String> {
+    table.cell(row, name).and_then(get_string)
+}
+
+fn number(table: &MoexHistory, row: &[MoexValue], name: &str) -> Option<f64> {
+    table.cell(row, name).and_then(get_value)
+}
+
+/// Instrument search and lookup backed by the ISS `securities` endpoints.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Catalog {
+    moex: Moex,
+}
+
+impl Catalog {
+    pub fn with_moex(moex: Moex) -> Catalog {
+        Catalog { moex }
+    }
+
+    /// Securities whose code, names or ISIN match `query`, ISS wants at least 3 characters.
+    pub async fn search_securities(&self, query: &str) -> Result<Vec<CatalogEntry>, MoexError> {
+        let url = format!("{}/iss/securities.json?q={}&iss.meta=off", self.moex.base_url(), percent_encode(query));
+        let d: MoexSecuritiesResponse = self.moex.get_json(url).await?;
+        let table: &MoexHistory = &d.securities;
+
+        Ok(
+            table.data.iter()
+                .filter_map(|row| Some(
+                    CatalogEntry {
+                        secid: text(table, row, "secid")?,
+                        short_name: text(table, row, "shortname").unwrap_or_default(),
+                        name: text(table, row, "name").unwrap_or_default(),
+                        is_traded: number(table, row, "is_traded").map(|v| v != 0.0).unwrap_or(false),
+                        group: text(table, row, "group").unwrap_or_default(),
+                        primary_board: text(table, row, "primary_boardid").unwrap_or_default(),
+                    }
+                ))
+                .collect()
+        )
+    }
+
+    /// All securities of the board, e.g. every TQBR share for the sidebar.
+    pub async fn board_securities(&self, board: &Board) -> Result<Vec<SecurityDescription>, MoexError> {
+        let url = format!("{}/iss/{}/securities.json?iss.only=securities&iss.meta=off", self.moex.base_url(), board.path());
+        let d: MoexSecuritiesResponse = self.moex.get_json(url).await?;
+        Ok(Self::descriptions(&d.securities, board))
+    }
+
+    pub async fn describe_security(&self, board: &Board, secid: &str) -> Result<SecurityDescription, MoexError> {
+        let url = format!("{}/iss/{}/securities/{}.json?iss.only=securities&iss.meta=off", self.moex.base_url(), board.path(), percent_encode(secid));
+        let d: MoexSecuritiesResponse = self.moex.get_json(url).await?;
+        Self::descriptions(&d.securities, board)
+            .into_iter()
+            .next()
+            .ok_or_else(|| MoexError::NoData(secid.to_string()))
+    }
+
+    fn descriptions(table: &MoexHistory, board: &Board) -> Vec<SecurityDescription> {
+        table.data.iter()
+            .filter_map(|row| Some(
+                SecurityDescription {
+                    secid: text(table, row, "SECID")?,
+                    board: Board::new(&board.engine, &board.market, &text(table, row, "BOARDID").unwrap_or_else(|| board.board.clone())),
+                    short_name: text(table, row, "SHORTNAME").unwrap_or_default(),
+                    // futures report the lot as LOTVOLUME
+                    lot_size: number(table, row, "LOTSIZE").or_else(|| number(table, row, "LOTVOLUME")).unwrap_or(1.0) as u32,
+                    decimals: number(table, row, "DECIMALS").unwrap_or(2.0) as u32,
+                    min_step: number(table, row, "MINSTEP").unwrap_or(0.01),
+                    currency: text(table, row, "CURRENCYID").or_else(|| text(table, row, "FACEUNIT")),
+                }
+            ))
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl Catalog {
+    pub fn new() -> Catalog {
+        Catalog::default()
+    }
+
+    pub fn with_config(config: MoexConfig) -> Catalog {
+        Catalog::with_moex(Moex::with_config(config))
+    }
+
+    /// Array of `CatalogEntry` matching the query.
+    pub async fn search(&self, query: &str) -> Result<js_sys::Array, JsValue> {
+        Ok(self.search_securities(query).await?.into_iter().map(JsValue::from).collect())
+    }
+
+    /// Array of `SecurityDescription` of every security on the board.
+    pub async fn list(&self, engine: &str, market: &str, board: &str) -> Result<js_sys::Array, JsValue> {
+        Ok(self.board_securities(&Board::new(engine, market, board)).await?.into_iter().map(JsValue::from).collect())
+    }
+
+    pub async fn describe(&self, engine: &str, market: &str, board: &str, secid: &str) -> Result<SecurityDescription, JsValue> {
+        Ok(self.describe_security(&Board::new(engine, market, board), secid).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stand_in::{ StandIn, query_param };
+
+    #[tokio::test]
+    async fn search_securities() {
+        let server = StandIn::serve(|_| (200, String::from(r#"{"securities": {
+            "columns": ["id", "secid", "shortname", "regnumber", "name", "isin", "is_traded", "emitent_id", "type", "group", "primary_boardid", "marketprice_boardid"],
+            "data": [
+                [2834, "GAZP", "ГАЗПРОМ ао", "1-02-00028-A", "Газпром (ПАО) ао", "RU0007661625", 1, 934, "common_share", "stock_shares", "TQBR", "TQBR"],
+                [3551, "GZX3", "GAZR-12.23", null, "Фьючерсный контракт GAZR-12.23", null, 0, null, "futures", "futures_forts", "RFUD", null]
+            ]}}"#)));
+
+        let catalog = Catalog::with_moex(Moex::with_base_url(server.url()));
+        let entries = catalog.search_securities("GAZ").await.unwrap();
+
+        assert_eq!(query_param(&server.requests()[0], "q"), Some("GAZ"));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].secid, "GAZP");
+        assert_eq!(entries[0].short_name, "ГАЗПРОМ ао");
+        assert!(entries[0].is_traded);
+        assert_eq!(entries[0].board(), Some(Board::shares()));
+        assert!(!entries[1].is_traded);
+        assert_eq!(entries[1].board(), Some(Board::futures()));
+        assert_eq!((entries[1].engine(), entries[1].market()), (String::from("futures"), String::from("forts")));
+
+        // the query is encoded, an ampersand does not end it
+        let _ = catalog.search_securities("AT&T").await;
+        assert_eq!(query_param(&server.requests()[1], "q"), Some("AT%26T"));
+        assert_eq!(query_param(&server.requests()[1], "iss.meta"), Some("off"));
+    }
+
+    #[tokio::test]
+    async fn describe_security() {
+        let server = StandIn::serve(|_| (200, String::from(r#"{"securities": {
+            "columns": ["SECID", "BOARDID", "SHORTNAME", "PREVPRICE", "LOTSIZE", "FACEVALUE", "DECIMALS", "MINSTEP", "CURRENCYID"],
+            "data": [["VTBR", "TQBR", "ВТБ ао", 0.024, 10000, 0.01, 6, 0.000005, "SUR"]]}}"#)));
+
+        let catalog = Catalog::with_moex(Moex::with_base_url(server.url()));
+        let description = catalog.describe_security(&Board::shares(), "VTBR").await.unwrap();
+
+        assert!(server.requests()[0].starts_with("/iss/engines/stock/markets/shares/boards/TQBR/securities/VTBR.json?iss.only=securities"));
+        assert_eq!(description.board, Board::shares());
+        assert_eq!(description.lot_size, 10000);
+        assert_eq!(description.decimals, 6);
+        assert_eq!(description.min_step, 0.000005);
+        assert_eq!(description.currency.as_deref(), Some("SUR"));
+
+        let server = StandIn::serve(|_| (200, String::from(r#"{"securities": {"columns": ["SECID", "BOARDID"], "data": []}}"#)));
+        let catalog = Catalog::with_moex(Moex::with_base_url(server.url()));
+        assert_eq!(catalog.describe_security(&Board::shares(), "NONE").await.err(), Some(MoexError::NoData(String::from("NONE"))));
+    }
+}
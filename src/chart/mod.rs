@@ -1,6 +1,7 @@
 use core::slice;
 use std::ops::{ Range, RangeBounds, Bound };
-use chrono::{ DateTime, Utc, TimeZone, NaiveDateTime, NaiveDate, NaiveTime };
+use serde::{ Serialize, Deserialize };
+use chrono::{ DateTime, Utc, TimeZone, Duration, NaiveDate, Datelike };
 use wasm_bindgen::prelude::*;
 use web_sys::{HtmlCanvasElement, WebGlRenderingContext, WebGlProgram, WebGlUniformLocation};
 
@@ -8,17 +9,58 @@ pub mod tradedata;
 mod shaders;
 
 use crate::moex;
-use tradedata::{ Hlocv, TradeData, TradeItemPositioned, union };
+use crate::calendar::{ self, TradingCalendar };
+use crate::cache::{ CachedSource, CacheStore, IndexedDbStore };
+use crate::live::{ self, LiveMode };
+use crate::corporate::CorporateActions;
+use crate::csv::{ self, CsvFormat };
+use crate::instrument::Instrument;
+use crate::kline::{ JsonSource, KlineMapping };
+use crate::source::{ DataSource, JsSource };
+use tradedata::{ GapPolicy, Hlocv, TradeColumns, TradeData, TradeExtras, TradeItem, union };
 
 const DEFAULT_CANDLE_INTERVAL: u32 = 12;
 const DEFAULT_CANDLE_RADIUS: u32 = 4;
+/// Part of the price range the highest turnover bar takes.
+const TURNOVER_HEIGHT: f64 = 0.25;
+/// Part of the price shown around bars that all trade at the same price.
+const FLAT_HEIGHT: f64 = 0.01;
 
+#[derive(Debug)]
 #[derive(Clone)]
-struct Period<Tz: TimeZone> {
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "DateTime<Tz>: Serialize", deserialize = "DateTime<Tz>: Deserialize<'de>"))]
+pub struct Period<Tz: TimeZone> {
     b: DateTime<Tz>,
     e: DateTime<Tz>,
 }
 
+impl<Tz: TimeZone> Period<Tz> {
+    pub fn new(b: DateTime<Tz>, e: DateTime<Tz>) -> Period<Tz> {
+        Period { b, e }
+    }
+    pub fn begin(&self) -> &DateTime<Tz> {
+        &self.b
+    }
+    pub fn end(&self) -> &DateTime<Tz> {
+        &self.e
+    }
+    pub fn contains(&self, d: &DateTime<Tz>) -> bool {
+        self.b <= *d && *d <= self.e
+    }
+    pub fn covers(&self, other: &Period<Tz>) -> bool {
+        self.b <= other.b && other.e <= self.e
+    }
+    pub fn overlaps(&self, other: &Period<Tz>) -> bool {
+        self.b <= other.e && other.b <= self.e
+    }
+    /// The same period on the clock of `tz`, e.g. `calendar::exchange_tz()`.
+    pub fn with_timezone<Tz2: TimeZone>(&self, tz: &Tz2) -> Period<Tz2> {
+        Period { b: self.b.with_timezone(tz), e: self.e.with_timezone(tz) }
+    }
+}
+
 impl Default for Period<Utc> {
     fn default() -> Self {
         Period { b: DateTime::<Utc>::MIN_UTC, e: DateTime::<Utc>::MIN_UTC }
@@ -36,6 +78,7 @@ impl Period<Utc> {
 #[derive(Debug)]
 #[derive(Clone, Default)]
 #[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
 pub struct RangeF32(Range<f32>);
 
 impl From<Range<f32>> for RangeF32 {
@@ -80,8 +123,9 @@ impl RangeF32 {
     pub fn size(&self) -> Option<f32> {
         if !self.is_empty() { Some(self.0.end - self.0.start) } else { None }
     }
+    /// First grid line and the distance between them, `None` for a range without a height.
     pub fn grid_start_step(&self) -> Option<(f32,f32)> {
-        self.size().map(|size| {
+        self.size().filter(|size| *size > 0.0).map(|size| {
             let l10: f32 = size.log10();
             let l10_round_1: f32 = l10.floor() - 1.0;
             let l10_fract: f32 = l10.fract();
@@ -103,6 +147,56 @@ impl RangeF32 {
     }
 }
 
+/// Range of prices, kept in `f64` so high-priced and long-decimal instruments keep their precision.
+#[derive(Debug)]
+#[derive(Clone, Default)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct RangeF64(Range<f64>);
+
+impl From<Range<f64>> for RangeF64 {
+    fn from(v: Range<f64>) -> RangeF64 {
+        RangeF64(v)
+    }
+}
+
+impl RangeF64 {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn new_with_max_rev() -> RangeF64 {
+        RangeF64::from(f64::MAX..f64::MIN)
+    }
+    pub fn consider(&mut self, l: f64, h: f64) {
+        if self.0.start > l { self.0.start = l; }
+        if self.0.end < h { self.0.end = h; }
+    }
+    pub fn start(&self) -> f64 {
+        self.0.start
+    }
+    pub fn end(&self) -> f64 {
+        self.0.end
+    }
+    pub fn size(&self) -> Option<f64> {
+        if !self.is_empty() { Some(self.0.end - self.0.start) } else { None }
+    }
+    /// Round price at or below the start to count the range from in `f32`: a multiple of the power
+    /// of ten above the size, so grid lines of the zoomed out range fall on round prices too.
+    pub fn origin(&self) -> f64 {
+        match self.size() {
+            Some(size) if size > 0.0 => {
+                let step: f64 = 10f64.powf(size.log10().floor() + 1.0);
+                (self.0.start / step).floor() * step
+            },
+            Some(_) => self.0.start,
+            None => 0.0,
+        }
+    }
+    /// The range counted from `origin`, in `f32` for the GPU.
+    pub fn relative_to(&self, origin: f64) -> RangeF32 {
+        RangeF32::from((self.0.start - origin) as f32..(self.0.end - origin) as f32)
+    }
+}
 
 #[derive(Debug)]
 #[derive(Clone, Default)]
@@ -137,23 +231,70 @@ impl Frame {
 }
 
 
+#[wasm_bindgen]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
 pub enum TradeInterval {
+    Minute1,
+    Minute10,
+    Hour,
     Day,
-    // Hour,
+    Week,
+    Month,
 }
 
 impl TradeInterval {
     pub fn _seconds(&self) -> u32 {
         match self {
-            Self::Day   => 86400,
-            // Self::Hour  => 3600,
+            Self::Minute1   => 60,
+            Self::Minute10  => 600,
+            Self::Hour      => 3600,
+            Self::Day       => 86400,
+            Self::Week      => 7 * 86400,
+            Self::Month     => 30 * 86400,
+        }
+    }
+    pub fn is_intraday(&self) -> bool {
+        matches!(self, Self::Minute1 | Self::Minute10 | Self::Hour)
+    }
+    /// Start of the bar of this interval `d` falls into. Days start at midnight
+    /// of the exchange clock, weeks start on Monday.
+    pub fn bar_start(&self, d: DateTime<Utc>) -> DateTime<Utc> {
+        let local: NaiveDate = calendar::exchange_local(&d).date();
+        let day: DateTime<Utc> = calendar::exchange_midnight(local);
+        match self {
+            Self::Minute1 | Self::Minute10 | Self::Hour => {
+                let seconds: i64 = self._seconds() as i64;
+                DateTime::from_timestamp(d.timestamp().div_euclid(seconds) * seconds, 0).unwrap_or(d)
+            },
+            Self::Day   => day,
+            Self::Week  => day - Duration::days(local.weekday().num_days_from_monday() as i64),
+            Self::Month => day - Duration::days(local.day0() as i64),
+        }
+    }
+    /// How far back `TradeChart::display` looks for the interval.
+    pub fn default_lookback(&self) -> Duration {
+        match self {
+            Self::Minute1   => Duration::days(1),
+            Self::Minute10  => Duration::days(10),
+            Self::Hour      => Duration::days(60),
+            Self::Day       => Duration::days(730),
+            Self::Week      => Duration::days(3650),
+            Self::Month     => Duration::days(7300),
         }
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct CandleOptions {
     interval: u32,
     radius: u32,
+    /// Line of the weighted average prices over the candles.
+    wap: bool,
+    /// Turnover bars at the bottom of the chart.
+    turnover: bool,
 }
 
 impl Default for CandleOptions {
@@ -161,6 +302,8 @@ impl Default for CandleOptions {
         CandleOptions {
             interval: DEFAULT_CANDLE_INTERVAL,
             radius: DEFAULT_CANDLE_RADIUS,
+            wap: false,
+            turnover: false,
         }
     }
 }
@@ -192,8 +335,16 @@ pub struct WebGlColor {
 }
 
 struct WebGlIndexes {
-    lines: Vec<u16>,
-    triangles: Vec<u16>,
+    lines: Vec<u32>,
+    triangles: Vec<u32>,
+}
+
+/// Lengths of the geometry buffers at some moment, e.g. before a candle was added.
+#[derive(Clone, Copy, Default)]
+struct GlMark {
+    points: usize,
+    lines: usize,
+    triangles: usize,
 }
 
 pub struct ChartGlData {
@@ -203,6 +354,19 @@ pub struct ChartGlData {
     frame: Frame,
     _interval: TradeInterval,
     candle_options: CandleOptions,
+    candle_marks: Vec<GlMark>,
+    candles_end: GlMark,
+    /// Price units per unit of turnover, fixed when the chart is built so appended bars match.
+    turnover_scale: f64,
+    /// Price the vertical coordinates are counted from, so they fit `f32` without losing the cents
+    /// of high-priced shares. Fixed when the chart is built like the turnover scale.
+    origin: f64,
+}
+
+impl Default for ChartGlData {
+    fn default() -> Self {
+        ChartGlData::new()
+    }
 }
 
 impl ChartGlData {
@@ -217,13 +381,25 @@ impl ChartGlData {
             frame: Frame::default(),
             _interval: TradeInterval::Day,
             candle_options: CandleOptions::default(),
+            candle_marks: Vec::new(),
+            candles_end: GlMark::default(),
+            turnover_scale: 0.0,
+            origin: 0.0,
         }
     }
-    pub fn from_trade_data(trade_data: TradeData, candle_options: CandleOptions) -> ChartGlData {
+    pub fn from_trade_data(trade_data: &TradeData, candle_options: CandleOptions) -> ChartGlData {
+        let origin: f64 = trade_data.range().origin();
         let frame: Frame = Frame::new(
             RangeF32::from(0.0..(trade_data.len() as u32 * candle_options.interval) as f32),
-            trade_data.range().clone(),
+            trade_data.range().relative_to(origin),
         );
+        let max_turnover: f64 = trade_data.columns().extras().iter().filter_map(|extras| extras.value).fold(0.0, f64::max);
+        let turnover_scale: f64 =
+            if max_turnover > 0.0 {
+                frame.height().unwrap_or(0.0) as f64 * TURNOVER_HEIGHT / max_turnover
+            } else {
+                0.0
+            };
 
         let mut data: ChartGlData =
             ChartGlData {
@@ -234,14 +410,57 @@ impl ChartGlData {
                     triangles: Vec::new(),
                 },
                 frame,
-                _interval: TradeInterval::Day,
+                _interval: *trade_data._interval(),
                 candle_options,
+                candle_marks: Vec::new(),
+                candles_end: GlMark::default(),
+                turnover_scale,
+                origin,
             };
 
         trade_data.visualize(&mut data);
 
         data
     }
+
+    /// Vertical coordinate of `price`.
+    fn y(&self, price: f64) -> f32 {
+        (price - self.origin) as f32
+    }
+
+    /// Price at the vertical coordinate `y`.
+    fn price(&self, y: f32) -> f64 {
+        self.origin + y as f64
+    }
+
+    fn mark(&self) -> GlMark {
+        GlMark {
+            points: self.points.len(),
+            lines: self.indexes.lines.len(),
+            triangles: self.indexes.triangles.len(),
+        }
+    }
+
+    /// Drops the geometry of the candles from `index` on along with everything added after them.
+    fn truncate_candles(&mut self, index: usize) {
+        let mark: GlMark = self.candle_marks.get(index).copied().unwrap_or(self.candles_end);
+        self.points.truncate(mark.points);
+        self.colors.truncate(mark.points);
+        self.indexes.lines.truncate(mark.lines);
+        self.indexes.triangles.truncate(mark.triangles);
+        self.candle_marks.truncate(index);
+        self.candles_end = mark;
+    }
+
+    /// Rebuilds only the candles from `from` on, after `TradeData::update_tail` changed them.
+    pub fn update_tail(&mut self, trade_data: &TradeData, from: usize) {
+        self.truncate_candles(from);
+        trade_data.visualize(self);
+        self.frame = Frame::new(
+            RangeF32::from(0.0..(trade_data.len() as u32 * self.candle_options.interval) as f32),
+            trade_data.range().relative_to(self.origin),
+        );
+    }
 }
 
 struct ChartGlView {
@@ -267,6 +486,9 @@ impl ChartGlView {
             .get_context("webgl")?
             .unwrap()
             .dyn_into::<WebGlRenderingContext>()?;
+        // candles, turnover bars and the average price line pass 65536 vertices within a few thousand bars
+        context.get_extension("OES_element_index_uint")?
+            .ok_or("32-bit vertex indices (OES_element_index_uint) are not supported")?;
 
         let program: WebGlProgram = shaders::make_shader_program(&context)?;
         context.use_program(Some(&program));
@@ -339,12 +561,12 @@ impl ChartGlView {
         Ok(())
     }
 
-    fn draw_indices(&self, indices: &[u16], item_type: u32) -> Result<(), JsValue> {
+    fn draw_indices(&self, indices: &[u32], item_type: u32) -> Result<(), JsValue> {
         let buffer_indices = self.context.create_buffer().ok_or("failed to create buffer")?;
         self.context.bind_buffer(WebGlRenderingContext::ELEMENT_ARRAY_BUFFER, Some(&buffer_indices));
 
         unsafe {
-            let indices_array = js_sys::Uint16Array::view(indices);
+            let indices_array = js_sys::Uint32Array::view(indices);
     
             self.context.buffer_data_with_array_buffer_view(
                 WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
@@ -356,7 +578,7 @@ impl ChartGlView {
         self.context.draw_elements_with_i32(
             item_type,
             indices.len() as i32,
-            WebGlRenderingContext::UNSIGNED_SHORT,
+            WebGlRenderingContext::UNSIGNED_INT,
             0,
         );
 
@@ -396,22 +618,163 @@ impl ChartGlView {
     }
 }
 
+/// How the displayed bars are made of the loaded ones without asking the source again.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+enum Resampling {
+    Interval(TradeInterval),
+    Days(usize),
+}
+
+impl Resampling {
+    fn apply(&self, trade_data: &TradeData) -> Option<TradeData> {
+        match self {
+            Self::Interval(interval) => trade_data.resampled(*interval),
+            Self::Days(days) => trade_data.resampled_days(*days),
+        }
+    }
+}
+
 #[wasm_bindgen]
 pub struct TradeChart {
     data: ChartGlData,
     view: ChartGlView,
+    source: Box<dyn DataSource>,
+    trade_data: TradeData,
+    /// Bars as loaded while the displayed ones are resampled from them.
+    loaded: Option<(TradeData, Resampling)>,
+    board: moex::Board,
+    instrument: Option<Instrument>,
+    gap_policy: GapPolicy,
+    /// Shows prices back-adjusted for splits and dividends.
+    adjusted: bool,
+    /// Corporate actions of the instrument with the key, loaded on demand.
+    actions: Option<(String, CorporateActions)>,
+    /// How ISS is reached for the data the source does not provide, like corporate actions.
+    config: moex::MoexConfig,
+    /// Trading days intraday bars are placed on, with the sessions of the market of the instrument.
+    calendar: TradingCalendar,
+    live: Option<LiveMode>,
 }
 
-#[wasm_bindgen]
 impl TradeChart {
-    pub fn new() -> Result<TradeChart, JsValue> {
+    /// Chart fed by any Rust `DataSource`.
+    pub fn with_source(source: Box<dyn DataSource>) -> Result<TradeChart, JsValue> {
         Ok(
             TradeChart {
                 data: ChartGlData::new(),
                 view: ChartGlView::new()?,
+                source,
+                trade_data: TradeData::new(TradeInterval::Day),
+                loaded: None,
+                board: moex::Board::default(),
+                instrument: None,
+                gap_policy: GapPolicy::default(),
+                adjusted: false,
+                actions: None,
+                config: moex::MoexConfig::default(),
+                calendar: TradingCalendar::moex(),
+                live: None,
             }
         )
     }
+}
+
+#[wasm_bindgen]
+impl TradeChart {
+    pub fn new() -> Result<TradeChart, JsValue> {
+        TradeChart::with_config(moex::MoexConfig::default())
+    }
+
+    /// Chart loading from ISS as set up by `config`, e.g. through a proxy or with a token.
+    pub fn with_config(config: moex::MoexConfig) -> Result<TradeChart, JsValue> {
+        let mut chart: TradeChart = TradeChart::with_source(Box::new(moex::Moex::with_config(config.clone())))?;
+        chart.config = config;
+        Ok(chart)
+    }
+
+    /// Selects the engine, market and board tickers are looked up on, e.g. `stock`, `bonds`, `TQOB`.
+    pub fn set_board(&mut self, engine: &str, market: &str, board: &str) {
+        self.board = moex::Board::new(engine, market, board);
+    }
+
+    /// Takes the bars from a JS callback instead of MOEX, see `JsSource`.
+    pub fn set_source(&mut self, callback: js_sys::Function) {
+        self.source = Box::new(JsSource::new(callback));
+    }
+
+    /// Takes the bars from a REST API described by a JSON `KlineMapping`, e.g.
+    /// `{"url": "https://api.example.com/klines?symbol={ticker}&interval={interval}&startTime={from}",
+    /// "date": 0, "open": 1, "high": 2, "low": 3, "close": 4, "volume": 5, "time_format": "unix_millis"}`.
+    pub fn set_json_source(&mut self, mapping: &str) -> Result<(), JsValue> {
+        let mapping: KlineMapping = KlineMapping::from_json(mapping).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.source = Box::new(JsonSource::new(mapping));
+        Ok(())
+    }
+
+    /// How days without trades are shown by the following `display` calls.
+    pub fn set_gap_policy(&mut self, policy: GapPolicy) {
+        self.gap_policy = policy;
+    }
+
+    /// Marks a `YYYY-MM-DD` date as a holiday of the exchange for the following `display` calls.
+    pub fn add_holiday(&mut self, date: &str) -> Result<(), JsValue> {
+        self.calendar.add_holiday(parse_day(date)?);
+        Ok(())
+    }
+
+    /// Marks a `YYYY-MM-DD` Saturday or Sunday as a trading day.
+    pub fn add_working_day(&mut self, date: &str) -> Result<(), JsValue> {
+        self.calendar.add_working_day(parse_day(date)?);
+        Ok(())
+    }
+
+    /// Trading days without trades in the displayed series, as `YYYY-MM-DD`.
+    pub fn missing_sessions(&self) -> Vec<String> {
+        self.trade_data.missing_sessions(&self.calendar)
+            .iter()
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .collect()
+    }
+
+    /// Column `open`, `high`, `low`, `close` or `volume` of the displayed bars, for indicators
+    /// or a WebGL upload on the JS side without a copy. The array views the memory of the module,
+    /// so it is only valid until the next call into the chart; `slice()` it to keep the values.
+    pub fn column(&self, name: &str) -> Result<js_sys::Float64Array, JsValue> {
+        let columns: &TradeColumns = self.trade_data.columns();
+        let column: &[f64] =
+            match name {
+                "open"      => columns.opens(),
+                "high"      => columns.highs(),
+                "low"       => columns.lows(),
+                "close"     => columns.closes(),
+                "volume"    => columns.volumes(),
+                _ => return Err(JsValue::from_str(&format!("no column {}", name))),
+            };
+        // Nothing is allocated between taking the view and handing it over, see `buffer_points`.
+        Ok(unsafe { js_sys::Float64Array::view(column) })
+    }
+
+    /// Labels for the time axis: about `count` pairs `[x, text]` of the bars in the view,
+    /// `x` counted in pixels from the left of the canvas and the text on the exchange clock.
+    pub fn time_labels(&self, count: u32) -> js_sys::Array {
+        let labels: js_sys::Array = js_sys::Array::new();
+        let frame: &RangeF32 = self.view.frame.range_x();
+        let size: f32 = match frame.size() { Some(size) if size > 0.0 => size, _ => return labels };
+        let width: f32 = self.view.canvas.width() as f32;
+        let step: f32 = self.data.candle_options.interval as f32;
+        let visible: Range<usize> = self.trade_data.index_range_x(frame, step);
+        let every: usize = (visible.len() / count.max(1) as usize).max(1);
+        for (i, item) in visible.step_by(every).filter_map(|i| Some((i, self.trade_data.item(i)?))) {
+            let x: f32 = (i as f32 * step - frame.start()) / size * width;
+            let label: js_sys::Array = js_sys::Array::new();
+            label.push(&JsValue::from_f64(x as f64));
+            label.push(&JsValue::from_str(&calendar::time_label(item.date(), *self.trade_data._interval())));
+            labels.push(&label);
+        }
+        labels
+    }
 
     pub fn draw(&mut self) -> Result<(), JsValue> {
         self.view.adjust_viewport()?;
@@ -419,15 +782,330 @@ impl TradeChart {
     }
 
     pub async fn display(&mut self, ticker: &str) -> Result<(), JsValue> {
+        self.display_interval(ticker, TradeInterval::Day).await
+    }
+
+    /// Displays the ticker on the selected board, the displayed instrument keeps its precision.
+    pub async fn display_interval(&mut self, ticker: &str, interval: TradeInterval) -> Result<(), JsValue> {
 
         // let js: JsValue = ticker.into();
         // web_sys::console::log_2(&"ticker = ".into(), &js);
 
-        let date_from: NaiveDateTime = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 12, 1).unwrap(), NaiveTime::default());
-        let trade_data: TradeData = moex::Moex::request_data(ticker, date_from).await.unwrap();
-        let mut data = ChartGlData::from_trade_data(trade_data, CandleOptions::default());
+        let instrument: Instrument =
+            match &self.instrument {
+                Some(instrument) if instrument.ticker == ticker && instrument.board == self.board => instrument.clone(),
+                _ => Instrument::with_board(ticker, self.board.clone()),
+            };
+        self.display_instrument(instrument, interval).await
+    }
+
+    /// Displays an instrument described by the page, e.g. made from a `SecurityDescription` of the `Catalog`.
+    pub async fn display_instrument(&mut self, instrument: Instrument, interval: TradeInterval) -> Result<(), JsValue> {
+        let trade_data: TradeData = self.load(&instrument, interval).await?;
+        self.loaded = None;
+        self.show(trade_data)?;
+        self.instrument = Some(instrument);
+        Ok(())
+    }
+
+    /// Loads the displayed instrument again, keeping the resampling.
+    async fn redisplay(&mut self) -> Result<(), JsValue> {
+        let instrument: Instrument =
+            match self.instrument.clone() {
+                Some(instrument) => instrument,
+                None => return Ok(()),
+            };
+        let trade_data: TradeData = self.load(&instrument, self.loaded_interval()).await?;
+        let resampled: Option<(Resampling, TradeData)> = self.loaded.as_ref()
+            .and_then(|(_, resampling)| Some((*resampling, resampling.apply(&trade_data)?)));
+        match resampled {
+            Some((resampling, resampled)) => {
+                self.loaded = Some((trade_data, resampling));
+                self.show(resampled)
+            },
+            None => {
+                self.loaded = None;
+                self.show(trade_data)
+            },
+        }
+    }
+
+    /// Bars of the instrument from the source as set up by the gap policy and the adjustment.
+    async fn load(&mut self, instrument: &Instrument, interval: TradeInterval) -> Result<TradeData, JsValue> {
+        let date_till: DateTime<Utc> = Utc::now();
+        let period: Period<Utc> = Period::new(date_till - interval.default_lookback(), date_till);
+        let mut trade_data: TradeData = self.source.fetch(instrument, interval, &period).await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        // a cached chunk may hold the instrument as it was known when fetched
+        trade_data.set_instrument(instrument.clone());
+        trade_data.fill_sessions(&self.calendar.for_market(&instrument.board.engine, &instrument.board.market));
+        trade_data.apply_gap_policy(self.gap_policy);
+        if trade_data.is_empty() {
+            return Err(JsValue::from_str(&format!("no trades for {} in the period", instrument.ticker)));
+        }
+        if self.adjusted {
+            trade_data = self.corporate_actions(instrument).await?.adjust(&trade_data);
+        }
+        Ok(trade_data)
+    }
+
+    /// Bars as loaded from the source, also while resampled ones are displayed.
+    fn loaded(&self) -> &TradeData {
+        self.loaded.as_ref().map(|(loaded, _)| loaded).unwrap_or(&self.trade_data)
+    }
+
+    /// Shows the loaded bars as bars of the coarser `interval` without asking the source again,
+    /// the interval of the loaded bars shows them as they are.
+    pub fn resample(&mut self, interval: TradeInterval) -> Result<(), JsValue> {
+        self.set_resampling(Resampling::Interval(interval))
+    }
+
+    /// Shows the loaded daily or intraday bars as bars of `days` trading days each.
+    pub fn resample_days(&mut self, days: u32) -> Result<(), JsValue> {
+        self.set_resampling(Resampling::Days(days.max(1) as usize))
+    }
+
+    /// Interval of the bars as loaded, the displayed ones may be resampled from them.
+    pub fn loaded_interval(&self) -> TradeInterval {
+        *self.loaded()._interval()
+    }
+
+    fn set_resampling(&mut self, resampling: Resampling) -> Result<(), JsValue> {
+        if resampling == Resampling::Interval(self.loaded_interval()) {
+            if let Some((loaded, _)) = self.loaded.take() {
+                self.show(loaded)?;
+            }
+            return Ok(());
+        }
+        let resampled: TradeData = resampling.apply(self.loaded())
+            .ok_or_else(|| JsValue::from_str("finer bars than the loaded ones have to be displayed anew"))?;
+        match self.loaded.as_mut() {
+            Some((_, current)) => *current = resampling,
+            None => self.loaded = Some((self.trade_data.clone(), resampling)),
+        }
+        self.show(resampled)
+    }
+
+    /// Actions of the instrument, asked from ISS unless loaded before or given by `load_actions_json`.
+    async fn corporate_actions(&mut self, instrument: &Instrument) -> Result<&CorporateActions, JsValue> {
+        let key: String = instrument.key();
+        if self.actions.as_ref().map(|(k, _)| k != &key).unwrap_or(true) {
+            let actions: CorporateActions = CorporateActions::request(&moex::Moex::with_config(self.config.clone()), &instrument.ticker).await?;
+            self.actions = Some((key, actions));
+        }
+        Ok(&self.actions.as_ref().unwrap().1)
+    }
 
-        let extra_space_y: f32 = data.frame.height().unwrap() * 0.5; 
+    /// Switches between the raw prices and the prices adjusted for splits and dividends,
+    /// the displayed instrument is loaded again. Bars added later by `refresh` or live mode
+    /// are not adjusted for actions happening after the load.
+    pub async fn set_adjusted(&mut self, adjusted: bool) -> Result<(), JsValue> {
+        self.adjusted = adjusted;
+        self.redisplay().await
+    }
+
+    pub fn is_adjusted(&self) -> bool {
+        self.adjusted
+    }
+
+    /// Takes the corporate actions of the displayed instrument from a JSON file instead of ISS,
+    /// see `CorporateActions::from_json` for the layout.
+    pub async fn load_actions_json(&mut self, text: &str) -> Result<(), JsValue> {
+        let instrument: Instrument = self.instrument.clone().ok_or_else(|| JsValue::from_str("no instrument is displayed"))?;
+        let actions: CorporateActions = CorporateActions::from_json(text).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.actions = Some((instrument.key(), actions));
+        if self.adjusted {
+            self.redisplay().await?;
+        }
+        Ok(())
+    }
+
+    pub fn instrument(&self) -> Option<Instrument> {
+        self.instrument.clone()
+    }
+
+    /// Price with the precision of the displayed instrument, for axis labels and readouts.
+    pub fn format_price(&self, price: f64) -> String {
+        self.trade_data.format_price(price)
+    }
+
+    /// Text about the bar at the horizontal position `x` of the canvas, counted in pixels from the left,
+    /// with the extras the source reported. `None` where there is no bar.
+    pub fn tooltip_at(&self, x: f32) -> Option<String> {
+        let frame: &RangeF32 = self.view.frame.range_x();
+        let width: f32 = self.view.canvas.width().max(1) as f32;
+        let idx: usize = self.trade_data.index_near_x(frame.start() + x / width * frame.size()?, self.data.candle_options.interval as f32)?;
+        let item: TradeItem = self.trade_data.item(idx)?;
+
+        let date_format: &str = if self.trade_data._interval().is_intraday() { "%Y-%m-%d %H:%M" } else { "%Y-%m-%d" };
+        let mut text: String = item.exchange_date().format(date_format).to_string();
+        if item.is_gap() {
+            text.push_str(" no trades");
+        } else {
+            let hlocv: &Hlocv = item.hlocv();
+            let price = |v: f64| self.format_price(v);
+            text.push_str(&format!(" O {} H {} L {} C {} V {}", price(hlocv.o), price(hlocv.h), price(hlocv.l), price(hlocv.c), hlocv.v));
+        }
+        let extras: &TradeExtras = item.extras();
+        if let Some(waprice) = extras.waprice {
+            text.push_str(&format!(" WAP {}", self.format_price(waprice)));
+        }
+        if let Some(value) = extras.value {
+            text.push_str(&format!(" turnover {:.0}", value));
+        }
+        if let Some(num_trades) = extras.num_trades {
+            text.push_str(&format!(" trades {}", num_trades));
+        }
+        if let Some(legal_close) = extras.legal_close {
+            text.push_str(&format!(" legal close {}", self.format_price(legal_close)));
+        }
+        Some(text)
+    }
+
+    /// Draws the line of the weighted average prices.
+    pub fn show_wap(&mut self, show: bool) -> Result<(), JsValue> {
+        self.data.candle_options.wap = show;
+        self.rebuild()
+    }
+
+    /// Draws the turnover as bars at the bottom of the chart.
+    pub fn show_turnover(&mut self, show: bool) -> Result<(), JsValue> {
+        self.data.candle_options.turnover = show;
+        self.rebuild()
+    }
+
+    /// Formatted price at the vertical position `y` of the canvas, counted in pixels from the top.
+    pub fn price_at(&self, y: f32) -> String {
+        let frame: &RangeF32 = self.view.frame.range_y();
+        let height: f32 = self.view.canvas.height().max(1) as f32;
+        self.format_price(self.data.price(frame.end() - y / height * frame.size().unwrap_or(0.0)))
+    }
+
+    /// Asks the source only for bars from the last loaded one on and appends them,
+    /// replacing the still forming last candle. Charts loaded from CSV are left as they are.
+    pub async fn refresh(&mut self) -> Result<(), JsValue> {
+        let instrument: Instrument =
+            match &self.instrument {
+                Some(instrument) if !self.trade_data.is_empty() => instrument.clone(),
+                _ => return Ok(()),
+            };
+        let interval: TradeInterval = self.loaded_interval();
+        let period: Period<Utc> = Period::new(*self.loaded().period().end(), Utc::now());
+        let mut fresh: TradeData = self.source.fetch(&instrument, interval, &period).await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        fresh.fill_sessions(&self.calendar.for_market(&instrument.board.engine, &instrument.board.market));
+        fresh.apply_gap_policy(self.gap_policy);
+
+        match self.loaded.as_mut() {
+            Some((loaded, _)) => {
+                loaded.update_tail(fresh);
+                self.resample_tail()
+            },
+            None => {
+                let from: usize = self.trade_data.update_tail(fresh);
+                self.show_tail(from)
+            },
+        }
+    }
+
+    /// Resamples anew after the loaded bars got a new tail. The bars before the last displayed one
+    /// are made of unchanged bars, the last one and the following are redrawn.
+    fn resample_tail(&mut self) -> Result<(), JsValue> {
+        let resampled: Option<TradeData> = self.loaded.as_ref().and_then(|(loaded, resampling)| resampling.apply(loaded));
+        if let Some(resampled) = resampled {
+            let from: usize = self.trade_data.len().saturating_sub(1);
+            self.trade_data = resampled;
+            self.show_tail(from)?;
+        }
+        Ok(())
+    }
+
+    /// Starts polling the last price of the displayed ticker every `interval_ms`.
+    /// The chart has no timer of its own: the page calls `poll_live` periodically
+    /// and the request goes out only when the interval has passed.
+    pub fn start_live(&mut self, interval_ms: u32) {
+        self.live = Some(LiveMode::new(interval_ms));
+    }
+
+    pub fn stop_live(&mut self) {
+        self.live = None;
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live.is_some()
+    }
+
+    /// Updates the current bar from the source's quote if live mode is on and a poll is due.
+    /// Resolves to `true` when a request was made.
+    pub async fn poll_live(&mut self) -> Result<bool, JsValue> {
+        let now: DateTime<Utc> = Utc::now();
+        let instrument: Instrument =
+            match (&self.live, &self.instrument) {
+                (Some(live), Some(instrument)) if live.is_due(now) && !self.trade_data.is_empty() => instrument.clone(),
+                _ => return Ok(false),
+            };
+        if let Some(live) = self.live.as_mut() {
+            live.polled(now);
+        }
+
+        // the quote goes into the loaded bars, resampled ones are made of them again
+        let trade_data: &mut TradeData = match self.loaded.as_mut() { Some((loaded, _)) => loaded, None => &mut self.trade_data };
+        let calendar: TradingCalendar = self.calendar.for_market(&instrument.board.engine, &instrument.board.market);
+        let from: Option<usize> = live::poll(self.source.as_ref(), &instrument, &calendar, trade_data).await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        match from {
+            Some(_) if self.loaded.is_some() => self.resample_tail()?,
+            Some(from) => self.show_tail(from)?,
+            None => {},
+        }
+        Ok(true)
+    }
+
+    /// Redraws after the bars from `from` on were changed or added.
+    fn show_tail(&mut self, from: usize) -> Result<(), JsValue> {
+        let old_end: f32 = self.data.frame.range_x().end();
+        self.data.update_tail(&self.trade_data, from);
+
+        // a view showing the last bar keeps following the new ones
+        if self.view.frame.range_x().end() >= old_end {
+            self.view.frame.range_x_mut().shift(self.data.frame.range_x().end() - old_end);
+        }
+        union(&self.data.frame, &self.view.frame).visualize(&mut self.data);
+
+        self.view.buffer_data(&self.data.points, &self.data.colors)?;
+
+        self.draw()
+    }
+
+    /// Displays bars parsed from CSV text, e.g. a file dropped by the user.
+    pub fn load_csv(&mut self, text: &str, format: &CsvFormat, interval: TradeInterval) -> Result<(), JsValue> {
+        let trade_data: TradeData = csv::parse(text, format, interval)?;
+        if trade_data.is_empty() {
+            return Err(JsValue::from_str("no bars in CSV"));
+        }
+        self.loaded = None;
+        self.show(trade_data)?;
+        self.instrument = None;
+        Ok(())
+    }
+
+    pub fn load_csv_bytes(&mut self, bytes: &[u8], format: &CsvFormat, interval: TradeInterval) -> Result<(), JsValue> {
+        self.load_csv(&String::from_utf8_lossy(bytes), format, interval)
+    }
+
+    fn show(&mut self, trade_data: TradeData) -> Result<(), JsValue> {
+        if trade_data.is_empty() {
+            return Err(JsValue::from_str("no bars to show"));
+        }
+        let mut data = ChartGlData::from_trade_data(&trade_data, self.data.candle_options);
+
+        // flat bars have no height of their own, a part of their price is shown around them
+        let height: f32 =
+            match data.frame.height() {
+                Some(height) if height > 0.0 => height,
+                _ => (data.origin.abs() * FLAT_HEIGHT) as f32,
+            };
+        let extra_space_y: f32 = if height > 0.0 { height * 0.5 } else { 1.0 };
         self.view.frame = Frame::new(
             data.frame.range_x().end() - self.view.canvas.width() as f32 .. data.frame.range_x().end(),
             data.frame.range_y().start() - extra_space_y .. data.frame.range_y().end() + extra_space_y,
@@ -436,6 +1114,7 @@ impl TradeChart {
         union(&data.frame, &self.view.frame).visualize(&mut data);
 
         self.data = data;
+        self.trade_data = trade_data;
 
         self.view.buffer_data(&self.data.points, &self.data.colors)?;
 
@@ -443,6 +1122,53 @@ impl TradeChart {
 
     }
 
+    /// Builds the geometry anew after the candle options changed, the view stays where it is.
+    fn rebuild(&mut self) -> Result<(), JsValue> {
+        if self.trade_data.is_empty() {
+            return Ok(());
+        }
+        let mut data = ChartGlData::from_trade_data(&self.trade_data, self.data.candle_options);
+        union(&data.frame, &self.view.frame).visualize(&mut data);
+        self.data = data;
+
+        self.view.buffer_data(&self.data.points, &self.data.colors)?;
+
+        self.draw()
+    }
+
+    /// Puts a cache in front of the current source, with `persistent` the history
+    /// is also kept in IndexedDB between sessions.
+    pub async fn enable_cache(&mut self, persistent: bool) -> Result<(), JsValue> {
+        let store: Option<Box<dyn CacheStore>> =
+            if persistent {
+                Some(Box::new(IndexedDbStore::open().await.map_err(|e| JsValue::from_str(&e.to_string()))?))
+            } else {
+                None
+            };
+        let source: Box<dyn DataSource> = std::mem::replace(&mut self.source, Box::new(moex::Moex::default()));
+        self.source = Box::new(CachedSource::new(source, store));
+        Ok(())
+    }
+
+    /// CSV of the whole series or, with `visible_only`, of the bars inside the view.
+    pub fn export_csv(&self, format: &CsvFormat, visible_only: bool) -> String {
+        csv::write(&self.export_items(visible_only), format)
+    }
+
+    pub fn export_json(&self, visible_only: bool) -> Result<String, JsValue> {
+        serde_json::to_string(&self.export_items(visible_only))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    fn export_items(&self, visible_only: bool) -> Vec<TradeItem> {
+        let columns: &TradeColumns = self.trade_data.columns();
+        if visible_only {
+            columns.rows(self.trade_data.index_range_x(self.view.frame.range_x(), self.data.candle_options.interval as f32)).collect()
+        } else {
+            columns.rows(0..columns.len()).collect()
+        }
+    }
+
     pub fn shift(&mut self, x: f32) -> Result<(), JsValue> {
 
         self.view.frame.range_x_mut().shift(x * self.data.candle_options.interval as f32);
@@ -452,6 +1178,11 @@ impl TradeChart {
 }
 
 
+/// Date of a `YYYY-MM-DD` string given by the page.
+fn parse_day(date: &str) -> Result<NaiveDate, JsValue> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| JsValue::from_str(&format!("bad date {}: {}", date, e)))
+}
+
 trait Visualize {
     fn visualize(&self, data: &mut ChartGlData);
 }
@@ -461,14 +1192,18 @@ impl Visualize for Frame {
         let line_color = WebGlColor { r: 0.99, g: 0.99, b: 0.99 };
         let z: f32 = -0.1;
 
-        let (mut y, grid_step) = self.range_y().grid_start_step().unwrap();
+        let (mut y, grid_step): (f32, f32) =
+            match self.range_y().grid_start_step() {
+                Some(grid) => grid,
+                None => return,
+            };
         while y < self.range_y().end() {
 
-            data.indexes.lines.push( data.points.len() as u16 );
+            data.indexes.lines.push( data.points.len() as u32 );
             data.points.push( Point {x: data.frame.range_x().start(), y, z } );
             data.colors.push( line_color.clone() );
 
-            data.indexes.lines.push( data.points.len() as u16 );
+            data.indexes.lines.push( data.points.len() as u32 );
             data.points.push( Point {x: data.frame.range_x().end(), y, z } );
             data.colors.push( line_color.clone() );
 
@@ -477,89 +1212,147 @@ impl Visualize for Frame {
     }
 }
 
-impl Visualize for TradeItemPositioned<'_> {
-    fn visualize(&self, data: &mut ChartGlData) {
-        let x: f32 = self.position as f32;
-        let width: f32 = data.candle_options.radius as f32;
-        let z: f32 = 0.0;
+/// Candle at `x` with the high, low, open and close already in the coordinates of `data`.
+fn visualize_candle(x: f32, (h, l, o, c): (f32, f32, f32, f32), data: &mut ChartGlData) {
+    let width: f32 = data.candle_options.radius as f32;
+    let z: f32 = 0.0;
+
+    if o == c {
+        let green_candle_color = WebGlColor { r: 0.1, g: 0.6, b: 0.1 };
+
+        data.indexes.lines.push( data.points.len() as u32 );
+        data.points.push( Point {x, y: h, z } );
+        data.colors.push( green_candle_color.clone() );
+
+        data.indexes.lines.push( data.points.len() as u32 );
+        data.points.push( Point {x, y: l, z } );
+        data.colors.push( green_candle_color.clone() );
+
+        data.indexes.lines.push( data.points.len() as u32 );
+        data.points.push( Point {x: x-width, y: o, z } );
+        data.colors.push( green_candle_color.clone() );
+
+        data.indexes.lines.push( data.points.len() as u32 );
+        data.points.push( Point {x: x+width, y: c, z } );
+        data.colors.push( green_candle_color );
+    } else {
+        let (body_high, body_low, candle_color): (f32, f32, WebGlColor) =
+            if o > c {
+                (o, c, WebGlColor { r: 0.9, g: 0.1, b: 0.1 } )
+            } else {
+                (c, o, WebGlColor { r: 0.1, g: 0.6, b: 0.1 } )
+            };
 
-        let hlocv: &Hlocv = self.item.hlocv();
-        if hlocv.o == hlocv.c {
-            let green_candle_color = WebGlColor { r: 0.1, g: 0.6, b: 0.1 };
+        if h > body_high || l < body_low {
+            let y1: f32 = 
+                if h > body_high {
+                    h
+                } else {
+                    body_low
+                };
+            let y2: f32 = 
+                if l < body_low {
+                    l
+                } else {
+                    body_high
+                };
+            
+            data.indexes.lines.push( data.points.len() as u32 );
+            data.points.push( Point { x, y: y1, z } );
+            data.colors.push( candle_color.clone() );
 
-            data.indexes.lines.push( data.points.len() as u16 );
-            data.points.push( Point {x, y: hlocv.h, z } );
-            data.colors.push( green_candle_color.clone() );
+            data.indexes.lines.push( data.points.len() as u32 );
+            data.points.push( Point { x, y: y2, z } );
+            data.colors.push( candle_color.clone() );
+        }
 
-            data.indexes.lines.push( data.points.len() as u16 );
-            data.points.push( Point {x, y: hlocv.l, z } );
-            data.colors.push( green_candle_color.clone() );
+        data.indexes.triangles.push( data.points.len() as u32 );
+        data.points.push( Point {x: x-width, y: o, z } );
+        data.colors.push( candle_color.clone() );
 
-            data.indexes.lines.push( data.points.len() as u16 );
-            data.points.push( Point {x: x-width, y: hlocv.o, z } );
-            data.colors.push( green_candle_color.clone() );
+        data.indexes.triangles.push( data.points.len() as u32 );
+        data.points.push( Point {x: x+width, y: o, z } );
+        data.colors.push( candle_color.clone() );
 
-            data.indexes.lines.push( data.points.len() as u16 );
-            data.points.push( Point {x: x+width, y: hlocv.c, z } );
-            data.colors.push( green_candle_color );
-        } else {
-            let (body_high, body_low, candle_color): (f32, f32, WebGlColor) =
-                if hlocv.o > hlocv.c {
-                    (hlocv.o, hlocv.c, WebGlColor { r: 0.9, g: 0.1, b: 0.1 } )
-                } else {
-                    (hlocv.c, hlocv.o, WebGlColor { r: 0.1, g: 0.6, b: 0.1 } )
-                };
+        data.indexes.triangles.push( data.points.len() as u32 );
+        data.points.push( Point {x: x+width, y: c, z } );
+        data.colors.push( candle_color.clone() );
 
-            if hlocv.h > body_high || hlocv.l < body_low {
-                let y1: f32 = 
-                    if hlocv.h > body_high {
-                        hlocv.h
-                    } else {
-                        body_low
-                    };
-                let y2: f32 = 
-                    if hlocv.l < body_low {
-                        hlocv.l
-                    } else {
-                        body_high
-                    };
-                
-                data.indexes.lines.push( data.points.len() as u16 );
-                data.points.push( Point { x, y: y1, z } );
-                data.colors.push( candle_color.clone() );
-
-                data.indexes.lines.push( data.points.len() as u16 );
-                data.points.push( Point { x, y: y2, z } );
-                data.colors.push( candle_color.clone() );
-            }
+        let idx: u32 = data.points.len() as u32;
+        data.indexes.triangles.push( idx-3 );
+        data.indexes.triangles.push( idx-1 );
+        data.indexes.triangles.push( idx );
+        data.points.push( Point {x: x-width, y: c, z } );
+        data.colors.push( candle_color );
+    }
+}
 
-            data.indexes.triangles.push( data.points.len() as u16 );
-            data.points.push( Point {x: x-width, y: hlocv.o, z } );
-            data.colors.push( candle_color.clone() );
+/// Turnover bar under the candle, drawn from the bottom of the price range.
+fn visualize_turnover(x: f32, value: Option<f64>, data: &mut ChartGlData) {
+    let value: f64 =
+        match value {
+            Some(value) if value > 0.0 && data.turnover_scale > 0.0 => value,
+            _ => return,
+        };
+    let width: f32 = data.candle_options.radius as f32;
+    let y1: f32 = data.frame.range_y().start();
+    let y2: f32 = y1 + (value * data.turnover_scale) as f32;
+    let z: f32 = -0.05;
+    let turnover_color = WebGlColor { r: 0.7, g: 0.7, b: 0.8 };
+
+    let idx: u32 = data.points.len() as u32;
+    for (x, y) in [(x-width, y1), (x+width, y1), (x+width, y2), (x-width, y2)] {
+        data.points.push( Point { x, y, z } );
+        data.colors.push( turnover_color.clone() );
+    }
+    data.indexes.triangles.extend_from_slice(&[idx, idx+1, idx+2, idx, idx+2, idx+3]);
+}
 
-            data.indexes.triangles.push( data.points.len() as u16 );
-            data.points.push( Point {x: x+width, y: hlocv.o, z } );
-            data.colors.push( candle_color.clone() );
+/// Segment of the weighted average price line from the previous candle at `x1` to this one at `x2`.
+fn visualize_wap((x1, previous): (f32, Option<f64>), (x2, waprice): (f32, Option<f64>), data: &mut ChartGlData) {
+    let (y1, y2): (f32, f32) =
+        match (previous, waprice) {
+            (Some(y1), Some(y2)) => (data.y(y1), data.y(y2)),
+            _ => return,
+        };
+    let z: f32 = 0.05;
+    let wap_color = WebGlColor { r: 0.1, g: 0.3, b: 0.9 };
 
-            data.indexes.triangles.push( data.points.len() as u16 );
-            data.points.push( Point {x: x+width, y: hlocv.c, z } );
-            data.colors.push( candle_color.clone() );
+    data.indexes.lines.push( data.points.len() as u32 );
+    data.points.push( Point { x: x1, y: y1, z } );
+    data.colors.push( wap_color.clone() );
 
-            let idx: u16 = data.points.len() as u16;
-            data.indexes.triangles.push( idx-3 );
-            data.indexes.triangles.push( idx-1 );
-            data.indexes.triangles.push( idx );
-            data.points.push( Point {x: x-width, y: hlocv.c, z } );
-            data.colors.push( candle_color );
-        }
-    }
+    data.indexes.lines.push( data.points.len() as u32 );
+    data.points.push( Point { x: x2, y: y2, z } );
+    data.colors.push( wap_color );
 }
 
+/// Adds the candles not yet present in `data`, so it also appends after `truncate_candles`.
+/// Reads the column slices, no bar is put together.
 impl Visualize for TradeData {
     fn visualize(&self, data: &mut ChartGlData) {
-        for (i,item) in self.iter_data().enumerate() {
-            TradeItemPositioned::new(&item, i as u32 * data.candle_options.interval).visualize(data);
+        let drawn: usize = data.candle_marks.len();
+        let step: u32 = data.candle_options.interval;
+        let columns: &TradeColumns = self.columns();
+        let (highs, lows, opens, closes): (&[f64], &[f64], &[f64], &[f64]) = (columns.highs(), columns.lows(), columns.opens(), columns.closes());
+        let extras: &[TradeExtras] = columns.extras();
+        for i in drawn..columns.len() {
+            data.candle_marks.push(data.mark());
+            let x: f32 = (i as u32 * step) as f32;
+            if data.candle_options.turnover {
+                visualize_turnover(x, extras[i].value, data);
+            }
+            // the place of a gap stays empty
+            if !columns.gaps()[i] {
+                let hloc: (f32, f32, f32, f32) = (data.y(highs[i]), data.y(lows[i]), data.y(opens[i]), data.y(closes[i]));
+                visualize_candle(x, hloc, data);
+            }
+            if data.candle_options.wap && i > 0 {
+                let previous: f32 = ((i as u32 - 1) * step) as f32;
+                visualize_wap((previous, extras[i - 1].waprice), (x, extras[i].waprice), data);
+            }
         }
+        data.candles_end = data.mark();
     }
 }
 
@@ -595,3 +1388,129 @@ impl Visualize for TradeData {
 //         // assert_eq!(calc_step_y( &Range::new(180.0, 200.0) ), 1.0);
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tradedata::TradeItem;
+
+    fn bars(days: std::ops::Range<u32>, close: f64) -> TradeData {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for d in days {
+            let o: f64 = d as f64;
+            trade_data.add_item(TradeItem::new(Utc.with_ymd_and_hms(2023, 3, d, 0, 0, 0).unwrap(), o.max(close) + 1.0, o.min(close) - 1.0, o, close, 10.0));
+        }
+        trade_data
+    }
+
+    type Geometry = (Vec<(f32, f32, f32)>, Vec<(f32, f32, f32)>, Vec<u32>, Vec<u32>);
+
+    fn geometry(data: &ChartGlData) -> Geometry {
+        (
+            data.points.iter().map(|p| (p.x, p.y, p.z)).collect(),
+            data.colors.iter().map(|c| (c.r, c.g, c.b)).collect(),
+            data.indexes.lines.clone(),
+            data.indexes.triangles.clone(),
+        )
+    }
+
+    #[test]
+    fn gaps_are_not_drawn() {
+        let mut trade_data: TradeData = bars(1..3, 3.0);
+        trade_data.add_item(TradeItem::gap(Utc.with_ymd_and_hms(2023, 3, 3, 0, 0, 0).unwrap(), 3.0));
+        trade_data.add_item(TradeItem::new(Utc.with_ymd_and_hms(2023, 3, 4, 0, 0, 0).unwrap(), 5.0, 3.0, 3.0, 4.0, 10.0));
+
+        let marked: ChartGlData = ChartGlData::from_trade_data(&trade_data, CandleOptions::default());
+        assert_eq!(marked.candle_marks.len(), 4);
+        assert_eq!(marked.candle_marks[2].points, marked.candle_marks[3].points);
+        // the candle after the gap keeps its place
+        assert_eq!(marked.points.last().unwrap().x, 3.0 * DEFAULT_CANDLE_INTERVAL as f32 - DEFAULT_CANDLE_RADIUS as f32);
+
+        trade_data.apply_gap_policy(GapPolicy::CarryForward);
+        let carried: ChartGlData = ChartGlData::from_trade_data(&trade_data, CandleOptions::default());
+        // a flat candle is a cross of two lines
+        assert_eq!(carried.candle_marks[3].points - carried.candle_marks[2].points, 4);
+    }
+
+    #[test]
+    fn overlays_follow_extras() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for d in 1..4 {
+            let extras = TradeExtras { waprice: Some(d as f64), value: Some(100.0 * d as f64), ..TradeExtras::default() };
+            trade_data.add_item(TradeItem::new(Utc.with_ymd_and_hms(2023, 3, d, 0, 0, 0).unwrap(), 4.0, 0.0, 1.0, 3.0, 10.0).with_extras(extras));
+        }
+        let plain: ChartGlData = ChartGlData::from_trade_data(&trade_data, CandleOptions::default());
+        let options = CandleOptions { wap: true, turnover: true, ..CandleOptions::default() };
+        let data: ChartGlData = ChartGlData::from_trade_data(&trade_data, options);
+
+        // a turnover bar for each candle and a WAP segment between each two
+        assert_eq!(data.points.len(), plain.points.len() + 3 * 4 + 2 * 2);
+        // the highest turnover takes a quarter of the price range
+        let top: f32 = data.points.iter().filter(|p| p.z == -0.05).map(|p| p.y).fold(f32::MIN, f32::max);
+        assert_eq!(top, 1.0);
+        let wap: Vec<f32> = data.points.iter().filter(|p| p.z == 0.05).map(|p| p.y).collect();
+        assert_eq!(wap, [1.0, 2.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn indexes_past_u16() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Minute1);
+        let start = Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap();
+        for i in 0..8000 {
+            let extras = TradeExtras { waprice: Some(2.0), value: Some(100.0), ..TradeExtras::default() };
+            trade_data.add_item(TradeItem::new(start + Duration::minutes(i), 4.0, 0.0, 1.0, 3.0, 10.0).with_extras(extras));
+        }
+        let options = CandleOptions { wap: true, turnover: true, ..CandleOptions::default() };
+        let data: ChartGlData = ChartGlData::from_trade_data(&trade_data, options);
+
+        assert!(data.points.len() > u16::MAX as usize);
+        let last: u32 = data.points.len() as u32 - 1;
+        assert_eq!(data.indexes.lines.iter().chain(data.indexes.triangles.iter()).max(), Some(&last));
+    }
+
+    #[test]
+    fn prices_drawn_from_origin() {
+        assert_eq!(RangeF64::from(15012.5..15230.0).origin(), 15000.0);
+        assert_eq!(RangeF64::from(0.021835..0.022415).origin(), 0.021);
+        assert_eq!(RangeF64::new_with_max_rev().origin(), 0.0);
+
+        // a tick of 0.5 at 2^24, about 16.8 million, is below the f32 resolution of the absolute prices
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        trade_data.add_item(TradeItem::new(Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap(), 16_777_217.0, 16_777_215.5, 16_777_216.0, 16_777_216.5, 10.0));
+        let data: ChartGlData = ChartGlData::from_trade_data(&trade_data, CandleOptions::default());
+        assert_eq!(data.origin, 16_777_210.0);
+        let ys: Vec<f32> = data.points.iter().map(|p| p.y).collect();
+        assert_eq!(ys, [7.0, 5.5, 6.0, 6.0, 6.5, 6.5]);
+        assert_eq!(data.frame.range_y(), &RangeF32::from(5.5..7.0));
+        assert_eq!(data.price(6.5), 16_777_216.5);
+    }
+
+    #[test]
+    fn flat_range_has_no_grid() {
+        assert_eq!(RangeF32::from(5.0..5.0).grid_start_step(), None);
+        let mut data: ChartGlData = ChartGlData::new();
+        Frame::new(0.0..10.0, 5.0..5.0).visualize(&mut data);
+        assert!(data.points.is_empty());
+    }
+
+    #[test]
+    fn update_tail_appends_geometry() {
+        let mut trade_data: TradeData = bars(1..6, 3.0);
+        let mut data: ChartGlData = ChartGlData::from_trade_data(&trade_data, CandleOptions::default());
+        // grid lines added after the candles are dropped by the update
+        union(&data.frame.clone(), &data.frame.clone()).visualize(&mut data);
+
+        // the last bar is still forming and comes again with another close
+        let from: usize = trade_data.update_tail(bars(5..8, 9.0));
+        assert_eq!(from, 4);
+        assert_eq!(trade_data.len(), 7);
+        assert_eq!(trade_data.range().end(), 10.0);
+        data.update_tail(&trade_data, from);
+
+        let rebuilt: ChartGlData = ChartGlData::from_trade_data(&trade_data, CandleOptions::default());
+        assert!(geometry(&data) == geometry(&rebuilt));
+        assert_eq!(data.frame, rebuilt.frame);
+        assert_eq!(data.candle_marks.len(), 7);
+    }
+}
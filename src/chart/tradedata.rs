@@ -1,17 +1,23 @@
-use core::slice::Iter;
-use chrono::{ DateTime, Utc, };
-use crate::chart::{ Period, RangeF32, Frame, TradeInterval };
+use std::{ cmp::Ordering, fmt, ops::Range };
+use serde::{ Serialize, Deserialize };
+use chrono::{ DateTime, NaiveDate, NaiveDateTime, SubsecRound, Utc, };
+use wasm_bindgen::prelude::*;
+use crate::calendar::{ self, TradingCalendar };
+use crate::chart::{ Period, RangeF32, RangeF64, Frame, TradeInterval };
+use crate::instrument::Instrument;
 
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct Hlocv {
-    pub h: f32,
-    pub l: f32,
-    pub o: f32,
-    pub c: f32,
-    pub v: f32,
+    pub h: f64,
+    pub l: f64,
+    pub o: f64,
+    pub c: f64,
+    pub v: f64,
 }
 
 impl Hlocv {
-    pub fn new(h: f32, l: f32, o: f32, c: f32, v: f32) -> Hlocv {
+    pub fn new(h: f64, l: f64, o: f64, c: f64, v: f64) -> Hlocv {
         Hlocv {
             h,
             l,
@@ -22,30 +28,169 @@ impl Hlocv {
     }
 }
 
+/// Values some sources report along with the bar.
+#[derive(Debug)]
+#[derive(Clone, Default)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct TradeExtras {
+    /// Weighted average price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub waprice: Option<f64>,
+    /// Turnover in the currency of the price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_trades: Option<u32>,
+    /// Official close price, set by the exchange also on days without trades.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legal_close: Option<f64>,
+}
+
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
 pub struct TradeItem {
     date: DateTime<Utc>,
+    #[serde(flatten)]
     hlocv: Hlocv,
+    #[serde(flatten)]
+    extras: TradeExtras,
+    /// No trades happened, the prices repeat the previous close.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    gap: bool,
 }
 
 impl TradeItem {
-    pub fn new(d: DateTime<Utc>, h: f32, l: f32, o: f32, c: f32, v: f32) -> TradeItem {
-        TradeItem { date: d, hlocv: Hlocv::new(h ,l, o, c, v) }
+    pub fn new(d: DateTime<Utc>, h: f64, l: f64, o: f64, c: f64, v: f64) -> TradeItem {
+        TradeItem { date: d, hlocv: Hlocv::new(h ,l, o, c, v), extras: TradeExtras::default(), gap: false }
+    }
+    /// Bar of a row vendors left some prices of empty: a missing open is taken from the close
+    /// and the other way round, a missing high or low from the two of them. `None` for a period
+    /// without trades, with no price at all or a zero volume; a missing volume is not known to be zero.
+    pub fn traded(d: DateTime<Utc>, h: Option<f64>, l: Option<f64>, o: Option<f64>, c: Option<f64>, v: Option<f64>) -> Option<TradeItem> {
+        if v == Some(0.0) {
+            return None;
+        }
+        let o: f64 = o.or(c).or(h).or(l)?;
+        let c: f64 = c.unwrap_or(o);
+        let h: f64 = h.unwrap_or_else(|| o.max(c));
+        let l: f64 = l.unwrap_or_else(|| o.min(c));
+        Some(TradeItem::new(d, h, l, o, c, v.unwrap_or(0.0)))
+    }
+    /// Bar of a period without trades, flat at the previous close.
+    pub fn gap(d: DateTime<Utc>, close: f64) -> TradeItem {
+        TradeItem { date: d, hlocv: Hlocv::new(close, close, close, close, 0.0), extras: TradeExtras::default(), gap: true }
+    }
+    pub fn with_extras(mut self, extras: TradeExtras) -> TradeItem {
+        self.extras = extras;
+        self
+    }
+    pub fn is_gap(&self) -> bool {
+        self.gap
     }
     pub fn _timestamp(&self) -> i64 {
         self.date.timestamp()
     }
+    pub fn date(&self) -> &DateTime<Utc> {
+        &self.date
+    }
+    /// Date and time of the bar on the exchange clock.
+    pub fn exchange_date(&self) -> NaiveDateTime {
+        calendar::exchange_local(&self.date)
+    }
     pub fn hlocv(&self) -> &Hlocv {
         &self.hlocv
     }
+    pub fn extras(&self) -> &TradeExtras {
+        &self.extras
+    }
+    /// Copy with the prices multiplied by `price` and the volume by `volume`, the turnover stays.
+    pub fn scaled(&self, price: f64, volume: f64) -> TradeItem {
+        let hlocv: &Hlocv = &self.hlocv;
+        TradeItem {
+            date: self.date,
+            hlocv: Hlocv::new(hlocv.h * price, hlocv.l * price, hlocv.o * price, hlocv.c * price, hlocv.v * volume),
+            extras: TradeExtras {
+                waprice: self.extras.waprice.map(|v| v * price),
+                legal_close: self.extras.legal_close.map(|v| v * price),
+                ..self.extras.clone()
+            },
+            gap: self.gap,
+        }
+    }
+}
+
+/// What to do with the bars of periods without trades.
+#[wasm_bindgen]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum GapPolicy {
+    /// Drop them, the neighbouring bars are drawn next to each other.
+    #[default]
+    Skip,
+    /// Keep them as flat bars at the previous close.
+    CarryForward,
+    /// Keep them as gaps, nothing is drawn in their place.
+    Mark,
+}
+
+/// Which bar stays when two series have one dated the same.
+#[derive(Debug)]
+#[derive(Clone, Copy, Default)]
+#[derive(PartialEq)]
+pub enum MergeRule {
+    /// The bar already there stays.
+    Keep,
+    /// The merged bar replaces it, e.g. a fresher fetch.
+    #[default]
+    Replace,
+    /// The merged bar replaces it unless it has no trades and the one there has.
+    PreferTraded,
+}
+
+impl MergeRule {
+    fn resolve(&self, old: TradeItem, new: TradeItem) -> TradeItem {
+        match self {
+            Self::Keep => old,
+            Self::Replace => new,
+            Self::PreferTraded => if new.gap && !old.gap { old } else { new },
+        }
+    }
 }
 
+/// Broken invariant of a `TradeData`.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum InvariantError {
+    /// The item at the index is not dated after the one before it.
+    Unordered(usize),
+    /// The period does not span the items.
+    Period,
+    /// The range does not span the prices of the items.
+    Range,
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unordered(idx)    => write!(f, "bar {} is not dated after the one before it", idx),
+            Self::Period            => write!(f, "period does not match the bars"),
+            Self::Range             => write!(f, "price range does not match the bars"),
+        }
+    }
+}
+
+impl std::error::Error for InvariantError {}
+
 pub struct TradeItemPositioned<'a> {
     pub item: &'a TradeItem,
     pub position: u32,
 }
 
 impl<'a> TradeItemPositioned<'a> {
-    pub fn new(item: &TradeItem, position: u32) -> TradeItemPositioned {
+    pub fn new(item: &TradeItem, position: u32) -> TradeItemPositioned<'_> {
         TradeItemPositioned {
             item,
             position
@@ -53,42 +198,472 @@ impl<'a> TradeItemPositioned<'a> {
     }
 }
 
+/// Bars stored column by column, the entries at an index of every column make one bar.
+/// The price and volume columns are contiguous `f64`, to scan in tight loops without a copy.
+/// Drawing converts the prices to `f32` relative to a local origin, see `ChartGlData`.
+#[derive(Clone)]
+#[derive(Default)]
+pub struct TradeColumns {
+    /// Starts of the bars in Unix seconds.
+    times: Vec<i64>,
+    opens: Vec<f64>,
+    highs: Vec<f64>,
+    lows: Vec<f64>,
+    closes: Vec<f64>,
+    volumes: Vec<f64>,
+    gaps: Vec<bool>,
+    extras: Vec<TradeExtras>,
+}
+
+impl TradeColumns {
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+    pub fn times(&self) -> &[i64] {
+        &self.times
+    }
+    pub fn opens(&self) -> &[f64] {
+        &self.opens
+    }
+    pub fn highs(&self) -> &[f64] {
+        &self.highs
+    }
+    pub fn lows(&self) -> &[f64] {
+        &self.lows
+    }
+    pub fn closes(&self) -> &[f64] {
+        &self.closes
+    }
+    pub fn volumes(&self) -> &[f64] {
+        &self.volumes
+    }
+    pub fn gaps(&self) -> &[bool] {
+        &self.gaps
+    }
+    pub fn extras(&self) -> &[TradeExtras] {
+        &self.extras
+    }
+    /// Start of the bar at `idx`.
+    pub fn date(&self, idx: usize) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(*self.times.get(idx)?, 0)
+    }
+    /// Bar at `idx` put together from the columns.
+    pub fn row(&self, idx: usize) -> Option<TradeItem> {
+        let date: DateTime<Utc> = self.date(idx)?;
+        Some(TradeItem {
+            date,
+            hlocv: Hlocv::new(self.highs[idx], self.lows[idx], self.opens[idx], self.closes[idx], self.volumes[idx]),
+            extras: self.extras[idx].clone(),
+            gap: self.gaps[idx],
+        })
+    }
+    pub fn rows(&self, range: Range<usize>) -> impl Iterator<Item = TradeItem> + '_ {
+        range.filter_map(move |idx| self.row(idx))
+    }
+    fn insert(&mut self, idx: usize, item: TradeItem) {
+        self.times.insert(idx, item.date.timestamp());
+        self.opens.insert(idx, item.hlocv.o);
+        self.highs.insert(idx, item.hlocv.h);
+        self.lows.insert(idx, item.hlocv.l);
+        self.closes.insert(idx, item.hlocv.c);
+        self.volumes.insert(idx, item.hlocv.v);
+        self.gaps.insert(idx, item.gap);
+        self.extras.insert(idx, item.extras);
+    }
+    fn push(&mut self, item: TradeItem) {
+        self.insert(self.len(), item);
+    }
+    fn set(&mut self, idx: usize, item: TradeItem) {
+        self.times[idx] = item.date.timestamp();
+        self.opens[idx] = item.hlocv.o;
+        self.highs[idx] = item.hlocv.h;
+        self.lows[idx] = item.hlocv.l;
+        self.closes[idx] = item.hlocv.c;
+        self.volumes[idx] = item.hlocv.v;
+        self.gaps[idx] = item.gap;
+        self.extras[idx] = item.extras;
+    }
+    fn truncate(&mut self, len: usize) {
+        self.times.truncate(len);
+        self.opens.truncate(len);
+        self.highs.truncate(len);
+        self.lows.truncate(len);
+        self.closes.truncate(len);
+        self.volumes.truncate(len);
+        self.gaps.truncate(len);
+        self.extras.truncate(len);
+    }
+    /// Drops the bars `keep` is false for.
+    fn retain(&mut self, keep: &[bool]) {
+        fn retain_column<T>(column: &mut Vec<T>, keep: &[bool]) {
+            let mut keep = keep.iter();
+            column.retain(|_| keep.next().copied().unwrap_or(true));
+        }
+        retain_column(&mut self.times, keep);
+        retain_column(&mut self.opens, keep);
+        retain_column(&mut self.highs, keep);
+        retain_column(&mut self.lows, keep);
+        retain_column(&mut self.closes, keep);
+        retain_column(&mut self.volumes, keep);
+        retain_column(&mut self.gaps, keep);
+        retain_column(&mut self.extras, keep);
+    }
+}
+
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(from = "TradeRows", into = "TradeRows")]
 pub struct TradeData {
+    _interval: TradeInterval,
+    columns: TradeColumns,
+    period: Period<Utc>,
+    range: RangeF64,
+    instrument: Option<Instrument>,
+}
+
+/// `TradeData` the way files and stores hold it, bar by bar.
+#[derive(Serialize, Deserialize)]
+struct TradeRows {
+    #[serde(rename = "interval")]
     _interval: TradeInterval,
     items: Vec<TradeItem>,
     period: Period<Utc>,
-    range: RangeF32,
+    range: RangeF64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instrument: Option<Instrument>,
+}
+
+impl From<TradeRows> for TradeData {
+    /// Takes the bars as they are, `TradeData::validate` tells whether they can be used.
+    fn from(rows: TradeRows) -> TradeData {
+        let mut columns: TradeColumns = TradeColumns::default();
+        rows.items.into_iter().for_each(|item| columns.push(item));
+        TradeData { _interval: rows._interval, columns, period: rows.period, range: rows.range, instrument: rows.instrument }
+    }
+}
+
+impl From<TradeData> for TradeRows {
+    fn from(trade_data: TradeData) -> TradeRows {
+        TradeRows {
+            _interval: trade_data._interval,
+            items: trade_data.iter_data().collect(),
+            period: trade_data.period,
+            range: trade_data.range,
+            instrument: trade_data.instrument,
+        }
+    }
 }
 
 impl TradeData {
     pub fn new(i: TradeInterval)-> TradeData {
         TradeData {
             _interval: i,
-            items: Vec::new(),
+            columns: TradeColumns::default(),
             period: Period::<Utc>::default(),
-            range: RangeF32::new_with_max_rev(),
+            range: RangeF64::new_with_max_rev(),
+            instrument: None,
         }
     }
-    pub fn add_item(&mut self, item: TradeItem) {
+    /// Adds the item in date order, an item dated like one already there replaces it.
+    /// Items arriving in order are appended without a search. Bars are dated to the second.
+    pub fn add_item(&mut self, mut item: TradeItem) {
+        item.date = item.date.trunc_subsecs(0);
+        let time: i64 = item.date.timestamp();
+        let idx: usize =
+            match self.columns.times.last() {
+                Some(last) if *last >= time => self.columns.times.partition_point(|t| *t < time),
+                _ => self.columns.len(),
+            };
+        if self.columns.times.get(idx) == Some(&time) {
+            self.columns.set(idx, item);
+            // the replaced bar may have held an extreme
+            self.recount();
+            return;
+        }
         self.period.consider(item.date);
         self.range.consider(item.hlocv.l, item.hlocv.h);
-        self.items.push(item);
+        self.columns.insert(idx, item);
+    }
+    /// Merges the bars of `other`, bars dated the same in both are resolved by `rule`.
+    /// The instrument stays, a series without one takes the one of `other`.
+    pub fn merge(&mut self, other: TradeData, rule: MergeRule) {
+        let columns: TradeColumns = std::mem::take(&mut self.columns);
+        let mut old = columns.rows(0..columns.len()).peekable();
+        let mut new = other.columns.rows(0..other.columns.len()).peekable();
+        loop {
+            let order: Option<Ordering> =
+                match (old.peek(), new.peek()) {
+                    (Some(a), Some(b)) => Some(a.date.cmp(&b.date)),
+                    (Some(_), None) => Some(Ordering::Less),
+                    (None, Some(_)) => Some(Ordering::Greater),
+                    (None, None) => None,
+                };
+            match order {
+                Some(Ordering::Less) => self.columns.push(old.next().unwrap()),
+                Some(Ordering::Greater) => self.columns.push(new.next().unwrap()),
+                Some(Ordering::Equal) => {
+                    if let (Some(a), Some(b)) = (old.next(), new.next()) {
+                        self.columns.push(rule.resolve(a, b));
+                    }
+                },
+                None => break,
+            }
+        }
+        self.recount();
+        if self.instrument.is_none() {
+            self.instrument = other.instrument;
+        }
+    }
+    /// Drops the items dated within `period`, both ends included.
+    pub fn remove(&mut self, period: &Period<Utc>) {
+        let removed: Range<usize> = self.index_range(period);
+        if removed.is_empty() {
+            return;
+        }
+        let keep: Vec<bool> = (0..self.len()).map(|idx| !removed.contains(&idx)).collect();
+        self.columns.retain(&keep);
+        self.recount();
+    }
+    /// Period and range the items span: the first and the last date, the lowest low and the highest high.
+    fn counted(&self) -> (Period<Utc>, RangeF64) {
+        let mut period: Period<Utc> = Period::<Utc>::default();
+        if let (Some(first), Some(last)) = (self.first(), self.last()) {
+            period = Period::new(first.date, last.date);
+        }
+        let low: f64 = self.columns.lows.iter().copied().fold(f64::MAX, f64::min);
+        let high: f64 = self.columns.highs.iter().copied().fold(f64::MIN, f64::max);
+        (period, RangeF64::from(low..high))
+    }
+    /// Period and range computed anew from the items.
+    fn recount(&mut self) {
+        let (period, range) = self.counted();
+        self.period = period;
+        self.range = range;
+    }
+    /// Checks what the methods keep true: the items are in strict date order, the period spans
+    /// the first and the last of them and the range their lows and highs. Series read from
+    /// files or stores are checked before use.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        if let Some(idx) = self.columns.times.windows(2).position(|pair| pair[0] >= pair[1]) {
+            return Err(InvariantError::Unordered(idx + 1));
+        }
+        let (period, range) = self.counted();
+        if period.begin() != self.period.begin() || period.end() != self.period.end() {
+            return Err(InvariantError::Period);
+        }
+        if range != self.range {
+            return Err(InvariantError::Range);
+        }
+        Ok(())
+    }
+    /// Merges bars fetched after the last loaded one. They replace the items dated from the
+    /// first fresh bar on, so a still forming last candle is overwritten.
+    /// Returns the index of the first replaced or added item.
+    pub fn update_tail(&mut self, fresh: TradeData) -> usize {
+        let first: i64 =
+            match fresh.columns.times.first() {
+                Some(time) => *time,
+                None => return self.len(),
+            };
+        let keep: usize = self.columns.times.partition_point(|time| *time < first);
+        self.columns.truncate(keep);
+        self.merge(fresh, MergeRule::Replace);
+        keep
+    }
+    /// Applies `policy` to the gap bars, sources always deliver them marked.
+    pub fn apply_gap_policy(&mut self, policy: GapPolicy) {
+        match policy {
+            GapPolicy::Skip => {
+                let traded: Vec<bool> = self.columns.gaps.iter().map(|gap| !gap).collect();
+                self.columns.retain(&traded);
+                self.recount();
+            },
+            GapPolicy::CarryForward => self.columns.gaps.iter_mut().for_each(|gap| *gap = false),
+            GapPolicy::Mark => {},
+        }
+    }
+    /// Places the bars on the session grid of `calendar`: every bar the exchange had between
+    /// the first and the last item and the source left out becomes a gap bar at the previous close.
+    /// Only intraday series are filled, longer bars already come with their gaps.
+    pub fn fill_sessions(&mut self, calendar: &TradingCalendar) {
+        if !self._interval.is_intraday() || self.is_empty() {
+            return;
+        }
+        let starts: Vec<DateTime<Utc>> = calendar.bar_starts(self._interval, &self.period);
+        let columns: TradeColumns = std::mem::take(&mut self.columns);
+        let mut starts = starts.into_iter().peekable();
+        for item in columns.rows(0..columns.len()) {
+            while let Some(start) = starts.next_if(|start| *start <= item.date) {
+                if start < item.date {
+                    let close: f64 = self.columns.closes.last().copied().unwrap_or(item.hlocv.o);
+                    self.columns.push(TradeItem::gap(start, close));
+                }
+            }
+            self.columns.push(item);
+        }
+        self.recount();
+    }
+    /// Trading days of `calendar` within the period of the data without a traded bar.
+    /// Bars longer than a day cover several sessions, there is nothing to tell for them.
+    pub fn missing_sessions(&self, calendar: &TradingCalendar) -> Vec<NaiveDate> {
+        if !(self._interval.is_intraday() || self._interval == TradeInterval::Day) || self.is_empty() {
+            return Vec::new();
+        }
+        let traded: Vec<NaiveDate> = self.iter_data()
+            .filter(|item| !item.gap)
+            .map(|item| item.exchange_date().date())
+            .collect();
+        calendar.trading_days(&self.period)
+            .into_iter()
+            .filter(|date| traded.binary_search(date).is_err())
+            .collect()
+    }
+    /// Bars of the coarser `interval` aggregated from these, each dated where `TradeInterval::bar_start`
+    /// puts its period. `None` for a finer interval, its bars have to be fetched.
+    pub fn resampled(&self, interval: TradeInterval) -> Option<TradeData> {
+        if interval._seconds() < self._interval._seconds() {
+            return None;
+        }
+        let mut trade_data: TradeData = TradeData::new(interval);
+        trade_data.instrument = self.instrument.clone();
+        let starts: Vec<DateTime<Utc>> = (0..self.len()).map(|idx| interval.bar_start(self.columns.date(idx).unwrap_or_default())).collect();
+        let mut begin: usize = 0;
+        for group in starts.chunk_by(|a, b| a == b) {
+            trade_data.add_item(aggregate(group[0], &self.columns, begin..begin + group.len()));
+            begin += group.len();
+        }
+        Some(trade_data)
+    }
+    /// Bars of `n` trading days each, counted from the first day of the series and dated by their
+    /// first day. Intraday bars are put together into days first, weeks and months give `None`.
+    pub fn resampled_days(&self, n: usize) -> Option<TradeData> {
+        let days: TradeData = self.resampled(TradeInterval::Day)?;
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        trade_data.instrument = days.instrument.clone();
+        for begin in (0..days.len()).step_by(n.max(1)) {
+            if let Some(date) = days.columns.date(begin) {
+                trade_data.add_item(aggregate(date, &days.columns, begin..(begin + n.max(1)).min(days.len())));
+            }
+        }
+        Some(trade_data)
     }
     pub fn len(&self) -> usize {
-        self.items.len()
+        self.columns.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+    /// The bars in date order, each put together from the columns.
+    pub fn iter_data(&self) -> impl Iterator<Item = TradeItem> + '_ {
+        self.columns.rows(0..self.len())
+    }
+    pub fn columns(&self) -> &TradeColumns {
+        &self.columns
+    }
+    pub fn item(&self, idx: usize) -> Option<TradeItem> {
+        self.columns.row(idx)
     }
-    pub fn iter_data(&self) -> Iter<TradeItem> {
-        self.items.iter()
+    pub fn first(&self) -> Option<TradeItem> {
+        self.item(0)
+    }
+    pub fn last(&self) -> Option<TradeItem> {
+        self.len().checked_sub(1).and_then(|idx| self.item(idx))
+    }
+    /// Index of the last item dated at or before `d`, `None` if all are later.
+    pub fn index_at(&self, d: &DateTime<Utc>) -> Option<usize> {
+        self.columns.times.partition_point(|time| *time <= d.timestamp()).checked_sub(1)
+    }
+    /// The bar `d` falls in: the last one started at or before it.
+    pub fn item_at(&self, d: &DateTime<Utc>) -> Option<TradeItem> {
+        self.index_at(d).and_then(|idx| self.item(idx))
+    }
+    /// Indexes of the items dated within `period`, both ends included.
+    pub fn index_range(&self, period: &Period<Utc>) -> Range<usize> {
+        // a bar dated within the second the period begins is before it
+        let begin: i64 = period.begin().timestamp() + i64::from(period.begin().timestamp_subsec_nanos() > 0);
+        let first: usize = self.columns.times.partition_point(|time| *time < begin);
+        let end: usize = self.columns.times.partition_point(|time| *time <= period.end().timestamp());
+        first..end.max(first)
+    }
+    /// Indexes of the items drawn within the horizontal extent `x` of a frame,
+    /// the item `i` being drawn at `i * step`.
+    pub fn index_range_x(&self, x: &RangeF32, step: f32) -> Range<usize> {
+        if step <= 0.0 || x.is_empty() {
+            return 0..0;
+        }
+        let first: usize = ((x.start() / step).ceil().max(0.0) as usize).min(self.len());
+        let end: usize = (((x.end() / step).floor() + 1.0).max(0.0) as usize).clamp(first, self.len());
+        first..end
+    }
+    /// Index of the item drawn nearest to `x`, `None` beyond half a step off the series.
+    pub fn index_near_x(&self, x: f32, step: f32) -> Option<usize> {
+        let position: f32 = x / step;
+        if step <= 0.0 || position < -0.5 {
+            return None;
+        }
+        Some(position.round() as usize).filter(|idx| *idx < self.len())
+    }
+    /// Copy holding only the items dated within `period`.
+    pub fn sliced(&self, period: &Period<Utc>) -> TradeData {
+        let mut trade_data: TradeData = TradeData::new(self._interval);
+        trade_data.instrument = self.instrument.clone();
+        self.columns.rows(self.index_range(period)).for_each(|item| trade_data.columns.push(item));
+        trade_data.recount();
+        trade_data
     }
     pub fn _interval(&self) -> &TradeInterval {
         &self._interval
     }
-    fn _period(&self) -> &Period<Utc> {
+    pub fn period(&self) -> &Period<Utc> {
         &self.period
     }
-    pub fn range(&self) -> &RangeF32 {
+    pub fn range(&self) -> &RangeF64 {
         &self.range
     }
+    /// Security the bars belong to, data loaded from files has none.
+    pub fn instrument(&self) -> Option<&Instrument> {
+        self.instrument.as_ref()
+    }
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = Some(instrument);
+    }
+    /// Price formatted with the precision of the instrument, two decimals without one.
+    pub fn format_price(&self, price: f64) -> String {
+        match &self.instrument {
+            Some(instrument) => instrument.format_price(price),
+            None => format!("{:.2}", price),
+        }
+    }
+}
+
+/// One bar made of the non-empty bars in the non-empty `range` of a longer period: the first open,
+/// the extremes, the last close and the totals. Bars without trades take no part unless there are
+/// only such, the result is then a gap at their close. The weighted average price is weighted by volume.
+fn aggregate(date: DateTime<Utc>, columns: &TradeColumns, range: Range<usize>) -> TradeItem {
+    let traded: Vec<usize> = range.clone().filter(|idx| !columns.gaps[*idx]).collect();
+    let (first, last): (usize, usize) =
+        match (traded.first(), traded.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return TradeItem::gap(date, columns.closes[range.end - 1]),
+        };
+    let high: f64 = traded.iter().map(|idx| columns.highs[*idx]).fold(f64::MIN, f64::max);
+    let low: f64 = traded.iter().map(|idx| columns.lows[*idx]).fold(f64::MAX, f64::min);
+    let volume: f64 = traded.iter().map(|idx| columns.volumes[*idx]).sum();
+
+    let weighted: Vec<(f64, f64)> = traded.iter().filter_map(|idx| columns.extras[*idx].waprice.map(|w| (w, columns.volumes[*idx]))).collect();
+    let weight: f64 = weighted.iter().map(|(_, v)| v).sum();
+    let sum = |values: Vec<f64>| if values.is_empty() { None } else { Some(values.iter().sum()) };
+    let extras: &[TradeExtras] = &columns.extras[range];
+    let extras: TradeExtras = TradeExtras {
+        waprice: if weight > 0.0 { Some(weighted.iter().map(|(w, v)| w * v).sum::<f64>() / weight) } else { None },
+        value: sum(extras.iter().filter_map(|extra| extra.value).collect()),
+        num_trades: extras.iter().filter_map(|extra| extra.num_trades).reduce(|a, b| a + b),
+        legal_close: extras.iter().rev().find_map(|extra| extra.legal_close),
+    };
+    TradeItem::new(date, high, low, columns.opens[first], columns.closes[last], volume).with_extras(extras)
 }
 
 pub fn union(a: &Frame, b: &Frame) -> Frame {
@@ -103,3 +678,214 @@ pub fn union(a: &Frame, b: &Frame) -> Frame {
     )
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn day(m: u32, d: u32) -> DateTime<Utc> {
+        calendar::exchange_midnight(NaiveDate::from_ymd_opt(2023, m, d).unwrap())
+    }
+
+    /// Daily bars from Wednesday 1 March to Tuesday 4 April 2023 without the weekends, the 8th March
+    /// passed without trades.
+    fn march() -> TradeData {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for (i, date) in NaiveDate::from_ymd_opt(2023, 3, 1).unwrap().iter_days().take(35).enumerate() {
+            let date: DateTime<Utc> = calendar::exchange_midnight(date);
+            let o: f64 = 100.0 + i as f64;
+            let item: TradeItem =
+                if calendar::exchange_local(&date).format("%d").to_string() == "08" {
+                    TradeItem::gap(date, o - 0.5)
+                } else {
+                    TradeItem::new(date, o + 2.0, o - 1.0, o, o + 0.5, 10.0)
+                        .with_extras(TradeExtras { waprice: Some(o), value: Some(1000.0), num_trades: Some(5), legal_close: Some(o + 0.5) })
+                };
+            if !matches!(calendar::exchange_local(&date).format("%a").to_string().as_str(), "Sat" | "Sun") {
+                trade_data.add_item(item);
+            }
+        }
+        trade_data
+    }
+
+    #[test]
+    fn resample_weeks_and_months() {
+        let weeks: TradeData = march().resampled(TradeInterval::Week).unwrap();
+        let dates: Vec<DateTime<Utc>> = weeks.iter_data().map(|item| *item.date()).collect();
+        // weeks start on Monday, the first one before the data
+        assert_eq!(dates, [day(2, 27), day(3, 6), day(3, 13), day(3, 20), day(3, 27), day(4, 3)]);
+        assert_eq!(*weeks._interval(), TradeInterval::Week);
+
+        // Mon 6th .. Fri 10th: opens 105..109, the 8th without trades
+        let week: &TradeItem = &weeks.item(1).unwrap();
+        assert_eq!((week.hlocv().o, week.hlocv().c, week.hlocv().h, week.hlocv().l, week.hlocv().v), (105.0, 109.5, 111.0, 104.0, 40.0));
+        assert_eq!(week.extras(), &TradeExtras { waprice: Some(107.0), value: Some(4000.0), num_trades: Some(20), legal_close: Some(109.5) });
+        assert_eq!(weeks.range().start(), 99.0);
+
+        let months: TradeData = march().resampled(TradeInterval::Month).unwrap();
+        assert_eq!(months.len(), 2);
+        assert_eq!(*months.item(1).unwrap().date(), day(4, 1));
+        assert_eq!(months.item(0).unwrap().hlocv().c, 130.5);
+        assert_eq!(months.item(1).unwrap().hlocv().o, 133.0);
+
+        // the bars are not split into finer ones
+        assert!(weeks.resampled(TradeInterval::Day).is_none());
+    }
+
+    #[test]
+    fn resample_days_and_intraday() {
+        let threes: TradeData = march().resampled_days(3).unwrap();
+        assert_eq!(threes.len(), 9);
+        assert_eq!(*threes.item(1).unwrap().date(), day(3, 6));
+        // the 6th, 7th and the 8th without trades: the close is the one of the 7th
+        assert_eq!(threes.item(1).unwrap().hlocv().c, 106.5);
+
+        let mut hours: TradeData = TradeData::new(TradeInterval::Hour);
+        for h in 10..14 {
+            hours.add_item(TradeItem::new(day(3, 1) + chrono::Duration::hours(h), 2.0 + h as f64, 1.0, 1.5, 1.0 + h as f64, 1.0));
+        }
+        hours.add_item(TradeItem::gap(day(3, 2) + chrono::Duration::hours(10), 14.0));
+        let days: TradeData = hours.resampled(TradeInterval::Day).unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!((days.item(0).unwrap().hlocv().h, days.item(0).unwrap().hlocv().c, days.item(0).unwrap().hlocv().v), (15.0, 14.0, 4.0));
+        // a day of gaps stays a gap
+        assert!(days.item(1).unwrap().is_gap());
+        assert_eq!(*days.item(1).unwrap().date(), day(3, 2));
+    }
+
+    fn bar(d: u32, c: f64) -> TradeItem {
+        TradeItem::new(day(3, d), c + 1.0, c - 1.0, c, c, 10.0)
+    }
+
+    #[test]
+    fn insert_in_order() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for (d, c) in [(3, 30.0), (1, 10.0), (5, 50.0), (2, 20.0), (5, 55.0)] {
+            trade_data.add_item(bar(d, c));
+        }
+        let closes: Vec<f64> = trade_data.iter_data().map(|item| item.hlocv().c).collect();
+        assert_eq!(closes, [10.0, 20.0, 30.0, 55.0]);
+        assert_eq!((*trade_data.period().begin(), *trade_data.period().end()), (day(3, 1), day(3, 5)));
+        assert_eq!(trade_data.validate(), Ok(()));
+
+        // the replaced bar held the high
+        trade_data.add_item(bar(5, 40.0));
+        assert_eq!(trade_data.range().end(), 41.0);
+        assert_eq!(trade_data.validate(), Ok(()));
+    }
+
+    #[test]
+    fn merge_by_rule() {
+        let mut old: TradeData = TradeData::new(TradeInterval::Day);
+        for item in [bar(1, 10.0), bar(2, 20.0), bar(3, 30.0)] {
+            old.add_item(item);
+        }
+        let mut new: TradeData = TradeData::new(TradeInterval::Day);
+        for item in [TradeItem::gap(day(3, 2), 10.0), bar(3, 33.0), bar(4, 40.0)] {
+            new.add_item(item);
+        }
+
+        let closes = |rule: MergeRule| -> Vec<f64> {
+            let mut merged: TradeData = old.clone();
+            merged.merge(new.clone(), rule);
+            assert_eq!(merged.validate(), Ok(()));
+            merged.iter_data().map(|item| item.hlocv().c).collect()
+        };
+        assert_eq!(closes(MergeRule::Keep), [10.0, 20.0, 30.0, 40.0]);
+        assert_eq!(closes(MergeRule::Replace), [10.0, 10.0, 33.0, 40.0]);
+        assert_eq!(closes(MergeRule::PreferTraded), [10.0, 20.0, 33.0, 40.0]);
+    }
+
+    #[test]
+    fn lookup_by_date() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        assert_eq!(trade_data.index_at(&day(3, 2)), None);
+        assert_eq!(trade_data.index_range(&Period::new(day(3, 1), day(3, 9))), 0..0);
+        for d in [2, 3, 6, 7] {
+            trade_data.add_item(bar(d, d as f64));
+        }
+        let hour = chrono::Duration::hours(1);
+
+        assert_eq!(trade_data.index_at(&(day(3, 2) - hour)), None);
+        assert_eq!(trade_data.index_at(&day(3, 2)), Some(0));
+        // the weekend falls in the Friday bar
+        assert_eq!(trade_data.item_at(&day(3, 5)).map(|item| item.hlocv().c), Some(3.0));
+        assert_eq!(trade_data.index_at(&day(3, 20)), Some(3));
+
+        let closes = |b: u32, e: u32| -> Vec<f64> {
+            trade_data.sliced(&Period::new(day(3, b), day(3, e))).iter_data().map(|item| item.hlocv().c).collect()
+        };
+        assert_eq!(closes(3, 6), [3.0, 6.0]);
+        assert_eq!(closes(4, 5), [] as [f64; 0]);
+        assert_eq!(closes(1, 30), [2.0, 3.0, 6.0, 7.0]);
+        assert_eq!(trade_data.index_range(&Period::new(day(3, 8), day(3, 9))), 4..4);
+        // a reversed period holds nothing
+        assert_eq!(trade_data.index_range(&Period::new(day(3, 7), day(3, 2))).len(), 0);
+
+        let sliced: TradeData = trade_data.sliced(&Period::new(day(3, 3), day(3, 6)));
+        assert_eq!((*sliced.period().begin(), *sliced.period().end()), (day(3, 3), day(3, 6)));
+        assert_eq!(sliced.validate(), Ok(()));
+    }
+
+    #[test]
+    fn lookup_by_position() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for d in 1..=5 {
+            trade_data.add_item(bar(d, d as f64));
+        }
+        let x = |b: f32, e: f32| RangeF32::from(b..e);
+        assert_eq!(trade_data.index_range_x(&x(0.0, 48.0), 12.0), 0..5);
+        assert_eq!(trade_data.index_range_x(&x(13.0, 36.0), 12.0), 2..4);
+        assert_eq!(trade_data.index_range_x(&x(-100.0, -1.0), 12.0), 0..0);
+        assert_eq!(trade_data.index_range_x(&x(100.0, 200.0), 12.0), 5..5);
+        assert_eq!(trade_data.index_range_x(&x(24.0, 10.0), 12.0), 0..0);
+
+        assert_eq!(trade_data.index_near_x(-5.0, 12.0), Some(0));
+        assert_eq!(trade_data.index_near_x(-7.0, 12.0), None);
+        assert_eq!(trade_data.index_near_x(19.0, 12.0), Some(2));
+        assert_eq!(trade_data.index_near_x(53.0, 12.0), Some(4));
+        assert_eq!(trade_data.index_near_x(55.0, 12.0), None);
+    }
+
+    #[test]
+    fn columns_and_rows() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for item in [bar(2, 20.0), bar(1, 10.0), TradeItem::gap(day(3, 3), 20.0)] {
+            trade_data.add_item(item);
+        }
+        let columns: &TradeColumns = trade_data.columns();
+        assert_eq!(columns.times(), [day(3, 1).timestamp(), day(3, 2).timestamp(), day(3, 3).timestamp()]);
+        assert_eq!(columns.highs(), [11.0, 21.0, 20.0]);
+        assert_eq!(columns.volumes(), [10.0, 10.0, 0.0]);
+        assert_eq!(columns.gaps(), [false, false, true]);
+        assert_eq!(*trade_data.last().unwrap().date(), day(3, 3));
+
+        // stored bar by bar, read back into columns
+        let json: String = serde_json::to_string(&trade_data).unwrap();
+        assert!(json.contains(r#""items":[{"date":"2023-02-28T21:00:00Z","h":11.0,"l":9.0,"o":10.0,"c":10.0,"v":10.0}"#));
+        let read: TradeData = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.columns().closes(), columns.closes());
+        assert_eq!(read.validate(), Ok(()));
+
+        let mut skipped: TradeData = trade_data.clone();
+        skipped.apply_gap_policy(GapPolicy::Skip);
+        assert_eq!(skipped.columns().closes(), [10.0, 20.0]);
+        assert_eq!(skipped.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_read_data() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for item in [bar(1, 10.0), bar(2, 20.0)] {
+            trade_data.add_item(item);
+        }
+        let json: String = serde_json::to_string(&trade_data).unwrap();
+        assert_eq!(serde_json::from_str::<TradeData>(&json).unwrap().validate(), Ok(()));
+
+        let swapped: String = json.replacen("2023-02-28", "2023-03-09", 1);
+        assert_eq!(serde_json::from_str::<TradeData>(&swapped).unwrap().validate(), Err(InvariantError::Unordered(1)));
+        let cheap: String = json.replacen("\"h\":21.0", "\"h\":2.0", 1);
+        assert_eq!(serde_json::from_str::<TradeData>(&cheap).unwrap().validate(), Err(InvariantError::Range));
+    }
+}
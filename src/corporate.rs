@@ -0,0 +1,222 @@
+use serde::{ Serialize, Deserialize };
+use chrono::{ DateTime, NaiveDate, Utc };
+use crate::calendar::{ self, TradingCalendar };
+use crate::http::percent_encode;
+use crate::moex::{ Moex, MoexError, MoexHistory, get_datetime, get_value };
+use crate::chart::tradedata::TradeData;
+
+/// Event changing the price of a share without a change of its value.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CorporateAction {
+    /// `before` shares became `after` shares from `date` on.
+    Split { date: DateTime<Utc>, before: f64, after: f64 },
+    /// Shares trade without the dividend `amount` from the ex-date `date` on.
+    Dividend { date: DateTime<Utc>, amount: f64 },
+}
+
+impl CorporateAction {
+    pub fn date(&self) -> &DateTime<Utc> {
+        match self {
+            Self::Split { date, .. } | Self::Dividend { date, .. } => date,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+pub struct MoexDividendsResponse {
+    pub dividends: MoexHistory,
+}
+
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+pub struct MoexSplitsResponse {
+    pub splits: MoexHistory,
+}
+
+/// Ex-date of a dividend with the registry closed on `d`: as many trading days before it as trades
+/// took to settle, two until MOEX moved to T+1 on 2023-07-31 and one since.
+fn ex_date(d: DateTime<Utc>) -> DateTime<Utc> {
+    let registry: NaiveDate = calendar::exchange_local(&d).date();
+    let settlement_days: usize = if registry < NaiveDate::from_ymd_opt(2023, 7, 31).unwrap() { 2 } else { 1 };
+    let trading: TradingCalendar = TradingCalendar::moex();
+    let ex_date: NaiveDate = (0..settlement_days).fold(registry, |date, _| trading.previous_trading_day(date));
+    calendar::exchange_midnight(ex_date)
+}
+
+/// Splits and dividends of a security, used to back-adjust its history.
+#[derive(Debug)]
+#[derive(Clone, Default)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CorporateActions {
+    actions: Vec<CorporateAction>,
+}
+
+impl CorporateActions {
+    pub fn new(actions: Vec<CorporateAction>) -> CorporateActions {
+        CorporateActions { actions }
+    }
+
+    /// Actions from a JSON array like `[{"type": "split", "date": "2021-07-06T00:00:00+03:00", "before": 1, "after": 100}]`.
+    pub fn from_json(text: &str) -> Result<CorporateActions, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    /// Dividends and splits of the security from the ISS statistics.
+    pub async fn request(moex: &Moex, secid: &str) -> Result<CorporateActions, MoexError> {
+        let url = format!("{}/iss/securities/{}/dividends.json?iss.meta=off", moex.base_url(), percent_encode(secid));
+        let d: MoexDividendsResponse = moex.get_json(url).await?;
+        let table: &MoexHistory = &d.dividends;
+        let mut actions: Vec<CorporateAction> = table.data.iter()
+            .filter_map(|row| Some(
+                CorporateAction::Dividend {
+                    date: ex_date(table.cell(row, "registryclosedate").and_then(get_datetime)?),
+                    amount: table.cell(row, "value").and_then(get_value)?,
+                }
+            ))
+            .collect();
+
+        let url = format!("{}/iss/statistics/engines/stock/splits/{}.json?iss.meta=off", moex.base_url(), percent_encode(secid));
+        let d: MoexSplitsResponse = moex.get_json(url).await?;
+        let table: &MoexHistory = &d.splits;
+        actions.extend(
+            table.data.iter()
+                .filter_map(|row| Some(
+                    CorporateAction::Split {
+                        date: table.cell(row, "tradedate").and_then(get_datetime)?,
+                        before: table.cell(row, "before").and_then(get_value)?,
+                        after: table.cell(row, "after").and_then(get_value)?,
+                    }
+                ))
+        );
+
+        Ok(CorporateActions { actions })
+    }
+
+    pub fn actions(&self) -> &[CorporateAction] {
+        &self.actions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Copy of `trade_data` with the bars before each action scaled so that the series has no jump at it.
+    /// The latest bars stay as traded, a dividend scales the earlier prices by `1 - amount / close`
+    /// with the last close before the ex-date.
+    pub fn adjust(&self, trade_data: &TradeData) -> TradeData {
+        let times: &[i64] = trade_data.columns().times();
+        // price and volume factors of every bar
+        let mut factors: Vec<(f64, f64)> = vec![(1.0, 1.0); times.len()];
+
+        for action in self.actions.iter() {
+            let first_after: usize = times.partition_point(|time| *time < action.date().timestamp());
+            if first_after == 0 || first_after == times.len() {
+                continue;
+            }
+            let factor: (f64, f64) =
+                match action {
+                    CorporateAction::Split { before, after, .. } if *before > 0.0 && *after > 0.0
+                        => (before / after, after / before),
+                    CorporateAction::Dividend { amount, .. } => {
+                        let close: f64 = trade_data.columns().closes()[first_after - 1];
+                        if close <= *amount {
+                            continue;
+                        }
+                        (1.0 - amount / close, 1.0)
+                    },
+                    _ => continue,
+                };
+            for f in factors[..first_after].iter_mut() {
+                f.0 *= factor.0;
+                f.1 *= factor.1;
+            }
+        }
+
+        let mut adjusted: TradeData = TradeData::new(*trade_data._interval());
+        if let Some(instrument) = trade_data.instrument() {
+            adjusted.set_instrument(instrument.clone());
+        }
+        for (item, (price, volume)) in trade_data.iter_data().zip(factors) {
+            adjusted.add_item(item.scaled(price, volume));
+        }
+        adjusted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart::{ TradeInterval, tradedata::TradeItem };
+    use crate::stand_in::StandIn;
+
+    fn day(d: u32) -> DateTime<Utc> {
+        calendar::exchange_midnight(NaiveDate::from_ymd_opt(2023, 3, d).unwrap())
+    }
+
+    #[test]
+    fn adjust_splits_and_dividends() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        for (d, c) in [(1, 400.0), (2, 400.0), (3, 100.0), (6, 50.0), (7, 50.0)] {
+            trade_data.add_item(TradeItem::new(day(d), c, c, c, c, 10.0));
+        }
+        let actions = CorporateActions::new(vec![
+            CorporateAction::Split { date: day(3), before: 1.0, after: 4.0 },
+            CorporateAction::Dividend { date: day(6), amount: 50.0 },
+            // outside of the history
+            CorporateAction::Dividend { date: day(20), amount: 5.0 },
+        ]);
+
+        let adjusted: TradeData = actions.adjust(&trade_data);
+        let closes: Vec<f64> = adjusted.iter_data().map(|item| item.hlocv().c).collect();
+        assert_eq!(closes, [50.0, 50.0, 50.0, 50.0, 50.0]);
+        let volumes: Vec<f64> = adjusted.iter_data().map(|item| item.hlocv().v).collect();
+        assert_eq!(volumes, [40.0, 40.0, 10.0, 10.0, 10.0]);
+        assert_eq!(adjusted.range().end(), 50.0);
+        // the raw series is left as it is
+        assert_eq!(trade_data.item(0).unwrap().hlocv().c, 400.0);
+    }
+
+    #[test]
+    fn actions_from_json() {
+        let actions = CorporateActions::from_json(r#"[
+            {"type": "split", "date": "2023-03-03T00:00:00+03:00", "before": 1, "after": 10},
+            {"type": "dividend", "date": "2023-03-05T21:00:00Z", "amount": 10.5}
+        ]"#).unwrap();
+        assert_eq!(actions.actions(), [
+            CorporateAction::Split { date: day(3), before: 1.0, after: 10.0 },
+            CorporateAction::Dividend { date: day(6), amount: 10.5 },
+        ]);
+        assert!(CorporateActions::from_json(r#"[{"type": "merger"}]"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn request_actions() {
+        let server = StandIn::serve(|target| {
+            if target.starts_with("/iss/securities/GMKN/dividends.json") {
+                (200, String::from(r#"{"dividends": {"columns": ["secid", "isin", "registryclosedate", "value", "currencyid"],
+                    "data": [["GMKN", "RU0007288411", "2023-03-06", 915.33, "RUB"], ["GMKN", "RU0007288411", "2024-01-15", 915.33, "RUB"]]}}"#))
+            } else {
+                (200, String::from(r#"{"splits": {"columns": ["tradedate", "secid", "before", "after"],
+                    "data": [["2024-04-04", "GMKN", 1, 100]]}}"#))
+            }
+        });
+        let moex = Moex::with_base_url(server.url());
+        let actions = CorporateActions::request(&moex, "GMKN").await.unwrap();
+
+        assert_eq!(actions.actions(), [
+            // the registry closed on Monday, with T+2 the ex-date is the Thursday before
+            CorporateAction::Dividend { date: day(2), amount: 915.33 },
+            // and with T+1 the Friday before
+            CorporateAction::Dividend { date: calendar::exchange_midnight(NaiveDate::from_ymd_opt(2024, 1, 12).unwrap()), amount: 915.33 },
+            CorporateAction::Split { date: calendar::exchange_midnight(NaiveDate::from_ymd_opt(2024, 4, 4).unwrap()), before: 1.0, after: 100.0 },
+        ]);
+        assert!(server.requests()[1].starts_with("/iss/statistics/engines/stock/splits/GMKN.json"));
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt;
+use chrono::{ NaiveDateTime, NaiveDate, NaiveTime, DateTime, Utc };
+use wasm_bindgen::prelude::*;
+use crate::calendar;
+use crate::chart::{
+    TradeInterval,
+    tradedata::{
+        Hlocv, TradeItem, TradeData,
+    }
+};
+
+/// Column of a CSV file, by header name or by zero based position.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum CsvColumn {
+    Name(String),
+    Index(usize),
+}
+
+impl From<&str> for CsvColumn {
+    fn from(v: &str) -> CsvColumn {
+        match v.trim().parse::<usize>() {
+            Ok(idx) => CsvColumn::Index(idx),
+            Err(_) => CsvColumn::Name(v.trim().to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CsvColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{}", name),
+            Self::Index(idx) => write!(f, "#{}", idx),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CsvColumns {
+    pub date: CsvColumn,
+    pub time: Option<CsvColumn>,
+    pub open: CsvColumn,
+    pub high: CsvColumn,
+    pub low: CsvColumn,
+    pub close: CsvColumn,
+    pub volume: CsvColumn,
+}
+
+#[derive(Debug)]
+pub enum CsvError {
+    /// The configured column is not in the header or beyond the row width.
+    MissingColumn(String),
+    /// A cell of the line can't be parsed, lines are numbered from 1.
+    BadRow { line: usize, column: String },
+    /// The column mapping string has a wrong number of entries.
+    BadMapping(String),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingColumn(name)   => write!(f, "CSV has no column {}", name),
+            Self::BadRow { line, column }
+                                        => write!(f, "bad value in column {} on line {}", column, line),
+            Self::BadMapping(mapping)   => write!(f, "bad CSV column mapping \"{}\"", mapping),
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<CsvError> for JsValue {
+    fn from(e: CsvError) -> JsValue {
+        JsValue::from_str(&e.to_string())
+    }
+}
+
+/// Layout of an OHLCV CSV/TSV file. The default is a Finam export:
+/// `<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>,<VOL>` with `20230301` dates and `100000` times.
+/// Dates and times are on the exchange clock both ways.
+#[wasm_bindgen]
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CsvFormat {
+    delimiter: char,
+    has_header: bool,
+    date_format: String,
+    time_format: Option<String>,
+    columns: CsvColumns,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        CsvFormat {
+            delimiter: ',',
+            has_header: true,
+            date_format: String::from("%Y%m%d"),
+            time_format: Some(String::from("%H%M%S")),
+            columns: CsvColumns {
+                date: CsvColumn::from("DATE"),
+                time: Some(CsvColumn::from("TIME")),
+                open: CsvColumn::from("OPEN"),
+                high: CsvColumn::from("HIGH"),
+                low: CsvColumn::from("LOW"),
+                close: CsvColumn::from("CLOSE"),
+                volume: CsvColumn::from("VOL"),
+            },
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl CsvFormat {
+    pub fn new() -> CsvFormat {
+        CsvFormat::default()
+    }
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = delimiter;
+    }
+    pub fn set_header(&mut self, has_header: bool) {
+        self.has_header = has_header;
+    }
+    /// `chrono` format of the date column, it may contain the time as well.
+    pub fn set_date_format(&mut self, date_format: &str) {
+        self.date_format = date_format.to_string();
+    }
+    pub fn set_time_format(&mut self, time_format: Option<String>) {
+        self.time_format = time_format;
+    }
+    /// Comma separated names or indexes in the order date, time, open, high, low, close, volume.
+    /// The time entry may be left out or set to `-` when the date column carries the time.
+    pub fn set_columns(&mut self, mapping: &str) -> Result<(), JsValue> {
+        self.columns = Self::parse_columns(mapping)?;
+        Ok(())
+    }
+}
+
+impl CsvFormat {
+    pub fn columns(&self) -> &CsvColumns {
+        &self.columns
+    }
+
+    fn parse_columns(mapping: &str) -> Result<CsvColumns, CsvError> {
+        let entries: Vec<&str> = mapping.split(',').map(str::trim).collect();
+        let (date, time, ohlcv): (&str, Option<&str>, &[&str]) =
+            match entries.len() {
+                7 => (entries[0], Some(entries[1]).filter(|t| *t != "-"), &entries[2..]),
+                6 => (entries[0], None, &entries[1..]),
+                _ => return Err(CsvError::BadMapping(mapping.to_string())),
+            };
+        Ok(
+            CsvColumns {
+                date: CsvColumn::from(date),
+                time: time.map(CsvColumn::from),
+                open: CsvColumn::from(ohlcv[0]),
+                high: CsvColumn::from(ohlcv[1]),
+                low: CsvColumn::from(ohlcv[2]),
+                close: CsvColumn::from(ohlcv[3]),
+                volume: CsvColumn::from(ohlcv[4]),
+            }
+        )
+    }
+
+    fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        line.split(self.delimiter)
+            .map(|cell| cell.trim().trim_matches('"'))
+            .collect()
+    }
+
+    fn parse_date(&self, date: &str, time: Option<&str>) -> Option<DateTime<Utc>> {
+        let naive: NaiveDateTime =
+            match (time, &self.time_format) {
+                (Some(time), Some(time_format)) => {
+                    let format: String = format!("{} {}", self.date_format, time_format);
+                    NaiveDateTime::parse_from_str(&format!("{} {}", date, time), &format).ok()?
+                },
+                _ => NaiveDateTime::parse_from_str(date, &self.date_format)
+                    .or_else(|_| NaiveDate::parse_from_str(date, &self.date_format).map(|d| NaiveDateTime::new(d, NaiveTime::default())))
+                    .ok()?,
+            };
+        Some(calendar::exchange_datetime(naive))
+    }
+}
+
+/// Header names are compared ignoring case and Finam's angle brackets.
+fn normalize(name: &str) -> String {
+    name.trim_start_matches('<').trim_end_matches('>').to_uppercase()
+}
+
+fn position(column: &CsvColumn, header: &[String]) -> Result<usize, CsvError> {
+    match column {
+        CsvColumn::Index(idx) => Ok(*idx),
+        CsvColumn::Name(name) => header.iter()
+            .position(|h| *h == normalize(name))
+            .ok_or_else(|| CsvError::MissingColumn(name.clone())),
+    }
+}
+
+pub fn parse(text: &str, format: &CsvFormat, interval: TradeInterval) -> Result<TradeData, CsvError> {
+    let mut lines = text.lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim_start_matches('\u{feff}')))
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let header: Vec<String> =
+        if format.has_header {
+            lines.next()
+                .map(|(_, line)| format.split(line).into_iter().map(normalize).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+    let columns: &CsvColumns = &format.columns;
+    let dpos: usize = position(&columns.date, &header)?;
+    let tpos: Option<usize> = columns.time.as_ref().map(|t| position(t, &header)).transpose()?;
+    let opos: usize = position(&columns.open, &header)?;
+    let hpos: usize = position(&columns.high, &header)?;
+    let lpos: usize = position(&columns.low, &header)?;
+    let cpos: usize = position(&columns.close, &header)?;
+    let vpos: usize = position(&columns.volume, &header)?;
+
+    let mut items: Vec<TradeItem> = Vec::new();
+    for (line, text) in lines {
+        let cells: Vec<&str> = format.split(text);
+        let cell = |pos: usize, column: &CsvColumn| {
+            cells.get(pos).copied().ok_or_else(|| CsvError::BadRow { line, column: column.to_string() })
+        };
+        let value = |pos: usize, column: &CsvColumn| {
+            cell(pos, column)?.parse::<f64>().map_err(|_| CsvError::BadRow { line, column: column.to_string() })
+        };
+
+        let time: Option<&str> =
+            match (tpos, &columns.time) {
+                (Some(pos), Some(column)) => Some(cell(pos, column)?),
+                _ => None,
+            };
+        let date: DateTime<Utc> = format.parse_date(cell(dpos, &columns.date)?, time)
+            .ok_or_else(|| CsvError::BadRow { line, column: columns.date.to_string() })?;
+
+        items.push(
+            TradeItem::new(
+                date,
+                value(hpos, &columns.high)?,
+                value(lpos, &columns.low)?,
+                value(opos, &columns.open)?,
+                value(cpos, &columns.close)?,
+                value(vpos, &columns.volume)?,
+            )
+        );
+    }
+
+    // exports often come newest first, sorted once the items are appended without a search;
+    // of lines dated the same the last one is kept, as `TradeData::add_item` would
+    items.sort_by_key(|item| *item.date());
+    items.dedup_by(|later, earlier| {
+        let same: bool = later.date() == earlier.date();
+        if same {
+            std::mem::swap(later, earlier);
+        }
+        same
+    });
+    let mut trade_data: TradeData = TradeData::new(interval);
+    items.into_iter().for_each(|item| trade_data.add_item(item));
+    Ok(trade_data)
+}
+
+/// Writes the items in the order date, time, open, high, low, close, volume using
+/// the delimiter and date/time formats of `format`, so `parse` reads the result back.
+pub fn write(items: &[TradeItem], format: &CsvFormat) -> String {
+    let mut columns: Vec<&str> = vec!["DATE"];
+    if format.time_format.is_some() {
+        columns.push("TIME");
+    }
+    columns.extend(["OPEN", "HIGH", "LOW", "CLOSE", "VOL"]);
+    let delimiter: String = format.delimiter.to_string();
+
+    let mut text: String = String::new();
+    if format.has_header {
+        text.push_str(&columns.iter().map(|c| format!("<{}>", c)).collect::<Vec<String>>().join(&delimiter));
+        text.push('\n');
+    }
+    for item in items {
+        let date: NaiveDateTime = item.exchange_date();
+        let mut cells: Vec<String> = vec![date.format(&format.date_format).to_string()];
+        if let Some(time_format) = &format.time_format {
+            cells.push(date.format(time_format).to_string());
+        }
+        let hlocv: &Hlocv = item.hlocv();
+        cells.extend([hlocv.o, hlocv.h, hlocv.l, hlocv.c, hlocv.v].iter().map(f64::to_string));
+        text.push_str(&cells.join(&delimiter));
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_finam() {
+        let text = "<TICKER>,<PER>,<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>,<VOL>\n\
+                    SBER,60,20230301,100000,211.5,212.8,210.9,212.1,1500000\n\
+                    SBER,60,20230301,110000,212.1,213.0,211.7,212.9,900000\n";
+        let trade_data = parse(text, &CsvFormat::default(), TradeInterval::Hour).unwrap();
+
+        assert_eq!(trade_data.len(), 2);
+        let item = trade_data.iter_data().nth(1).unwrap();
+        assert_eq!(item._timestamp(), NaiveDateTime::parse_from_str("2023-03-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp());
+        assert_eq!(item.hlocv().o, 212.1);
+        assert_eq!(item.hlocv().v, 900000.0);
+    }
+
+    #[test]
+    fn parse_newest_first() {
+        let mut format = CsvFormat::new();
+        format.set_header(false);
+        format.set_date_format("%Y-%m-%d");
+        format.columns = CsvFormat::parse_columns("0,-,1,2,3,4,5").unwrap();
+
+        let text = "2023-03-03,1,3,1,3,10\n2023-03-02,1,2,1,2,10\n2023-03-01,1,1,1,1,10\n2023-03-02,1,5,1,5,10\n";
+        let trade_data = parse(text, &format, TradeInterval::Day).unwrap();
+        let closes: Vec<f64> = trade_data.iter_data().map(|item| item.hlocv().c).collect();
+        assert_eq!(closes, [1.0, 5.0, 3.0]);
+        assert!(trade_data.validate().is_ok());
+    }
+
+    #[test]
+    fn parse_tsv_by_index() {
+        let mut format = CsvFormat::new();
+        format.set_delimiter('\t');
+        format.set_header(false);
+        format.set_date_format("%Y-%m-%d");
+        format.columns = CsvFormat::parse_columns("0,-,1,2,3,4,5").unwrap();
+
+        let text = "2023-03-01\t100\t105\t99\t104\t10\n\n2023-03-02\t104\t106\t101\t102\t12\n";
+        let trade_data = parse(text, &format, TradeInterval::Day).unwrap();
+        assert_eq!(trade_data.len(), 2);
+        assert_eq!(trade_data.iter_data().next().unwrap().hlocv().h, 105.0);
+        assert_eq!(trade_data.range().start(), 99.0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let format = CsvFormat::default();
+        let text = "<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>\n20230301,100000,1,2,0.5,1.5\n";
+        assert!(matches!(parse(text, &format, TradeInterval::Day), Err(CsvError::MissingColumn(c)) if c == "VOL"));
+
+        let text = "<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>,<VOL>\n20230301,100000,1,2,0.5,1.5,10\n20230302,100000,1,n/a,0.5,1.5,10\n";
+        assert!(matches!(parse(text, &format, TradeInterval::Day), Err(CsvError::BadRow { line: 3, column }) if column == "HIGH"));
+
+        assert!(CsvFormat::parse_columns("DATE,OPEN,HIGH").is_err());
+    }
+
+    #[test]
+    fn write_reads_back() {
+        let text = "<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>,<VOL>\n20230301,100000,211.5,212.8,210.9,212.1,1500000\n";
+        let format = CsvFormat::default();
+        let trade_data = parse(text, &format, TradeInterval::Hour).unwrap();
+        let items: Vec<TradeItem> = trade_data.iter_data().collect();
+        assert_eq!(write(&items, &format), text);
+
+        let json = serde_json::to_string(&items).unwrap();
+        assert_eq!(json, r#"[{"date":"2023-03-01T07:00:00Z","h":212.8,"l":210.9,"o":211.5,"c":212.1,"v":1500000.0}]"#);
+
+        // high-priced and long-decimal prices keep all their digits
+        let text = "<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>,<VOL>\n20230301,100000,16543.37,16580.01,16500.99,16577.73,12345\n20230301,110000,0.021835,0.022415,0.021805,0.02239,6213450000\n";
+        let trade_data = parse(text, &format, TradeInterval::Hour).unwrap();
+        assert_eq!(trade_data.item(1).unwrap().hlocv().c, 0.02239);
+        assert_eq!(write(&trade_data.iter_data().collect::<Vec<TradeItem>>(), &format), text);
+    }
+}
@@ -0,0 +1,449 @@
+use std::{ cell::Cell, fmt, future::Future, pin::Pin, rc::Rc, task::Poll };
+use chrono::{ DateTime, Duration, Utc };
+
+const DEFAULT_TIMEOUT_SECONDS: i64 = 30;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF_MS: i64 = 500;
+/// Longest wait between two attempts however many retries are allowed.
+const MAX_BACKOFF_SECONDS: i64 = 60;
+const DEFAULT_BUCKET_CAPACITY: f64 = 5.0;
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 5.0;
+const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+/// How often a request waiting for a free slot looks again.
+const SLOT_POLL_MS: i64 = 10;
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum HttpError {
+    /// The request did not reach the server or the connection broke.
+    Network(String),
+    /// The server answered with a non-success status code.
+    Status(u16),
+    /// No complete answer within the timeout.
+    Timeout,
+}
+
+impl HttpError {
+    /// Server errors and failed connections may go away, client errors will not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Network(_) | Self::Timeout => true,
+            Self::Status(status) => *status >= 500,
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Network(e)        => write!(f, "network error: {}", e),
+            Self::Status(status)    => write!(f, "server responded with HTTP status {}", status),
+            Self::Timeout           => write!(f, "request timed out"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(e: reqwest::Error) -> HttpError {
+        match e.status() {
+            Some(status) => HttpError::Status(status.as_u16()),
+            None => HttpError::Network(e.to_string()),
+        }
+    }
+}
+
+/// Token bucket: holds up to `capacity` requests and gets `per_second` new ones every second.
+/// It also keeps at most `max_in_flight` requests going at once, a slow ISS does not pile them up.
+/// Clients sharing one through an `Rc` are limited together.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: Cell<f64>,
+    updated: Cell<Option<DateTime<Utc>>>,
+    max_in_flight: usize,
+    in_flight: Cell<usize>,
+}
+
+/// Place of a request in flight, given back to the bucket when dropped.
+pub struct Slot<'a>(&'a TokenBucket);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.set(self.0.in_flight.get() - 1);
+    }
+}
+
+impl Default for TokenBucket {
+    fn default() -> Self {
+        TokenBucket::new(DEFAULT_BUCKET_CAPACITY, DEFAULT_REQUESTS_PER_SECOND)
+    }
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, per_second: f64) -> TokenBucket {
+        TokenBucket {
+            capacity,
+            per_second,
+            tokens: Cell::new(capacity),
+            updated: Cell::new(None),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            in_flight: Cell::new(0),
+        }
+    }
+
+    /// At most `max_in_flight` requests at once, at least one.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> TokenBucket {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Slot for one more request, `None` while the maximum is in flight.
+    pub fn enter(&self) -> Option<Slot<'_>> {
+        if self.in_flight.get() >= self.max_in_flight {
+            return None;
+        }
+        self.in_flight.set(self.in_flight.get() + 1);
+        Some(Slot(self))
+    }
+
+    /// Takes a token at `now`. Without one available returns how long to wait before trying again.
+    pub fn take(&self, now: DateTime<Utc>) -> Result<(), Duration> {
+        let elapsed: f64 = self.updated.get().map(|updated| (now - updated).num_milliseconds().max(0) as f64 / 1000.0).unwrap_or(0.0);
+        let tokens: f64 = (self.tokens.get() + elapsed * self.per_second).min(self.capacity);
+        self.updated.set(Some(now));
+
+        if tokens >= 1.0 {
+            self.tokens.set(tokens - 1.0);
+            Ok(())
+        } else {
+            self.tokens.set(tokens);
+            let wait: f64 = (1.0 - tokens) / self.per_second;
+            Err(Duration::milliseconds((wait * 1000.0).ceil() as i64))
+        }
+    }
+}
+
+/// `value` made safe for a query parameter or a path segment: every byte but the
+/// unreserved letters, digits and `-._~` is written as `%XX`.
+pub fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Waits for `duration` without blocking, on the tokio timer natively and on `setTimeout` in the browser.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration.to_std().unwrap_or_default()).await
+}
+
+/// `setTimeout` is looked up on the global scope, a window and a worker alike. Where there is none
+/// the wait ends at once rather than never.
+#[cfg(target_arch = "wasm32")]
+pub async fn sleep(duration: Duration) {
+    use wasm_bindgen::{ JsCast, JsValue };
+    let ms: i32 = duration.num_milliseconds().clamp(0, i32::MAX as i64) as i32;
+    let global: js_sys::Object = js_sys::global();
+    let set_timeout: Option<js_sys::Function> = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout")).ok()
+        .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let scheduled: bool = set_timeout.as_ref()
+            .map(|set_timeout| set_timeout.call2(&global, &resolve, &JsValue::from(ms)).is_ok())
+            .unwrap_or(false);
+        if !scheduled {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+/// Output of `future`, or `None` if `duration` passed first.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let mut future: Pin<Box<F>> = Box::pin(future);
+    let mut timer = Box::pin(sleep(duration));
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        timer.as_mut().poll(cx).map(|_| None)
+    }).await
+}
+
+/// HTTP client with a timeout per attempt, retries with exponential backoff
+/// on transient errors and a token bucket limiting the request rate and the requests in flight.
+pub struct HttpClient {
+    client: reqwest::Client,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    bucket: Rc<TokenBucket>,
+    headers: Vec<(String, String)>,
+    basic_auth: Option<(String, String)>,
+    bearer_token: Option<String>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new()
+    }
+}
+
+impl HttpClient {
+    pub fn new() -> HttpClient {
+        HttpClient {
+            client: reqwest::Client::new(),
+            timeout: Duration::seconds(DEFAULT_TIMEOUT_SECONDS),
+            retries: DEFAULT_RETRIES,
+            backoff: Duration::milliseconds(DEFAULT_BACKOFF_MS),
+            bucket: Rc::new(TokenBucket::default()),
+            headers: Vec::new(),
+            basic_auth: None,
+            bearer_token: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Number of repeated attempts after the first one, the n-th waits `backoff * 2^(n-1)`
+    /// but never longer than a minute.
+    pub fn set_retries(&mut self, retries: u32, backoff: Duration) {
+        self.retries = retries;
+        self.backoff = backoff;
+    }
+
+    /// Allows bursts of `capacity` requests and `per_second` requests a second on average.
+    pub fn set_rate_limit(&mut self, capacity: f64, per_second: f64) {
+        self.bucket = Rc::new(TokenBucket::new(capacity, per_second));
+    }
+
+    /// Takes the tokens from `bucket`, so the requests of all clients sharing it are limited together.
+    pub fn share_rate_limit(&mut self, bucket: Rc<TokenBucket>) {
+        self.bucket = bucket;
+    }
+
+    /// Header sent with every request.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn set_basic_auth(&mut self, user: &str, password: &str) {
+        self.basic_auth = Some((user.to_string(), password.to_string()));
+    }
+
+    /// Token sent as `Authorization: Bearer <token>`.
+    pub fn set_bearer_token(&mut self, token: &str) {
+        self.bearer_token = Some(token.to_string());
+    }
+
+    fn request(&self, url: &str) -> reqwest::RequestBuilder {
+        let mut request = self.client.get(url);
+        for (name, value) in self.headers.iter() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some((user, password)) = &self.basic_auth {
+            request = request.basic_auth(user, Some(password));
+        }
+        if let Some(token) = &self.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        request
+    }
+
+    /// Wait before the repeated attempt following `attempt` failed ones.
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let max: Duration = Duration::seconds(MAX_BACKOFF_SECONDS);
+        2i32.checked_pow(attempt)
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .map_or(max, |delay| delay.min(max))
+    }
+
+    async fn acquire(&self) -> Slot<'_> {
+        let slot: Slot =
+            loop {
+                match self.bucket.enter() {
+                    Some(slot) => break slot,
+                    None => sleep(Duration::milliseconds(SLOT_POLL_MS)).await,
+                }
+            };
+        while let Err(wait) = self.bucket.take(Utc::now()) {
+            sleep(wait).await;
+        }
+        slot
+    }
+
+    async fn attempt(&self, url: &str) -> Result<String, HttpError> {
+        let _slot: Slot = self.acquire().await;
+        let request = async {
+            let response = self.request(url).send().await?;
+            if !response.status().is_success() {
+                return Err(HttpError::Status(response.status().as_u16()));
+            }
+            Ok(response.text().await?)
+        };
+        timeout(self.timeout, request).await.unwrap_or(Err(HttpError::Timeout))
+    }
+
+    /// Body of a successful GET of `url`.
+    pub async fn get_text(&self, url: &str) -> Result<String, HttpError> {
+        let mut attempt: u32 = 0;
+        loop {
+            match self.attempt(url).await {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    sleep(self.backoff_delay(attempt)).await;
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{ Arc, atomic::{ AtomicUsize, Ordering } };
+    use chrono::TimeZone;
+    use crate::stand_in::StandIn;
+
+    /// Answers the first `failures` requests with `status`, the others with `ok`.
+    fn flaky(failures: usize, status: u16) -> StandIn {
+        let count: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        StandIn::serve(move |_| {
+            if count.fetch_add(1, Ordering::SeqCst) < failures {
+                (status, String::from("busy"))
+            } else {
+                (200, String::from("ok"))
+            }
+        })
+    }
+
+    fn client(retries: u32) -> HttpClient {
+        let mut client = HttpClient::new();
+        client.set_retries(retries, Duration::milliseconds(1));
+        client.set_rate_limit(100.0, 1000.0);
+        client
+    }
+
+    #[test]
+    fn encodes_percent() {
+        assert_eq!(percent_encode("GAZP"), "GAZP");
+        assert_eq!(percent_encode("AT&T q=1"), "AT%26T%20q%3D1");
+        assert_eq!(percent_encode("a/b?c"), "a%2Fb%3Fc");
+        assert_eq!(percent_encode("Газ"), "%D0%93%D0%B0%D0%B7");
+    }
+
+    #[test]
+    fn bucket_refills() {
+        let start: DateTime<Utc> = Utc.with_ymd_and_hms(2023, 3, 1, 10, 0, 0).unwrap();
+        let bucket = TokenBucket::new(2.0, 4.0);
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Err(Duration::milliseconds(250)));
+        assert_eq!(bucket.take(start + Duration::milliseconds(125)), Err(Duration::milliseconds(125)));
+        assert_eq!(bucket.take(start + Duration::milliseconds(250)), Ok(()));
+        // never more than the capacity
+        let later: DateTime<Utc> = start + Duration::seconds(60);
+        assert_eq!(bucket.take(later), Ok(()));
+        assert_eq!(bucket.take(later), Ok(()));
+        assert!(bucket.take(later).is_err());
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let mut client = HttpClient::new();
+        client.set_retries(100, Duration::milliseconds(500));
+        assert_eq!(client.backoff_delay(0), Duration::milliseconds(500));
+        assert_eq!(client.backoff_delay(3), Duration::seconds(4));
+        assert_eq!(client.backoff_delay(10), Duration::seconds(MAX_BACKOFF_SECONDS));
+        assert_eq!(client.backoff_delay(31), Duration::seconds(MAX_BACKOFF_SECONDS));
+        assert_eq!(client.backoff_delay(99), Duration::seconds(MAX_BACKOFF_SECONDS));
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let server = flaky(2, 503);
+        assert_eq!(client(3).get_text(server.url()).await, Ok(String::from("ok")));
+        assert_eq!(server.requests().len(), 3);
+
+        let server = flaky(5, 500);
+        assert_eq!(client(2).get_text(server.url()).await, Err(HttpError::Status(500)));
+        assert_eq!(server.requests().len(), 3);
+
+        // a client error is final
+        let server = flaky(1, 404);
+        assert_eq!(client(3).get_text(server.url()).await, Err(HttpError::Status(404)));
+        assert_eq!(server.requests().len(), 1);
+
+        let error = client(1).get_text("http://127.0.0.1:1/").await.err().unwrap();
+        assert!(matches!(error, HttpError::Network(_)));
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let server = StandIn::serve(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            (200, String::from("late"))
+        });
+        let mut client = client(0);
+        client.set_timeout(Duration::milliseconds(50));
+        assert_eq!(client.get_text(server.url()).await, Err(HttpError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn limits_rate() {
+        let server = flaky(0, 200);
+        let mut client = client(0);
+        client.set_rate_limit(1.0, 20.0);
+
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            client.get_text(server.url()).await.unwrap();
+        }
+        // one request from the bucket, two more after 50 ms each
+        assert!(start.elapsed() >= std::time::Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn limits_requests_in_flight() {
+        let server = flaky(0, 200);
+        let bucket: Rc<TokenBucket> = Rc::new(TokenBucket::new(100.0, 1000.0).with_max_in_flight(1));
+        let mut client = client(0);
+        client.share_rate_limit(bucket.clone());
+
+        // the one slot is taken, the request waits for it
+        let slot: Option<Slot> = bucket.enter();
+        assert!(slot.is_some());
+        assert!(bucket.enter().is_none());
+        assert_eq!(timeout(Duration::milliseconds(50), client.get_text(server.url())).await, None);
+        assert!(server.requests().is_empty());
+
+        drop(slot);
+        assert_eq!(client.get_text(server.url()).await, Ok(String::from("ok")));
+        // and gives it back when done
+        assert!(bucket.enter().is_some());
+    }
+
+    #[tokio::test]
+    async fn shares_rate_limit() {
+        let server = flaky(0, 200);
+        // a second client sharing the bucket waits for the tokens the first one took
+        let bucket: Rc<TokenBucket> = Rc::new(TokenBucket::new(1.0, 20.0));
+        let (mut first, mut second) = (client(0), client(0));
+        first.share_rate_limit(bucket.clone());
+        second.share_rate_limit(bucket);
+        let start = std::time::Instant::now();
+        for client in [&first, &second, &first, &second] {
+            client.get_text(server.url()).await.unwrap();
+        }
+        assert!(start.elapsed() >= std::time::Duration::from_millis(140));
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{ Serialize, Deserialize };
+use wasm_bindgen::prelude::*;
+use crate::catalog::SecurityDescription;
+use crate::moex::Board;
+
+const DEFAULT_DECIMALS: u32 = 2;
+const DEFAULT_TICK_SIZE: f64 = 0.01;
+
+/// Traded security: where it is traded and how its prices are quoted.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct Instrument {
+    pub ticker: String,
+    #[wasm_bindgen(skip)]
+    pub board: Board,
+    /// Number of decimals prices are shown with.
+    pub decimals: u32,
+    /// Minimal price step.
+    pub tick_size: f64,
+    pub lot_size: u32,
+    pub currency: Option<String>,
+}
+
+impl Instrument {
+    /// Instrument with the usual precision of two decimals, until the exchange tells better.
+    pub fn with_board(ticker: &str, board: Board) -> Instrument {
+        Instrument {
+            ticker: ticker.to_string(),
+            board,
+            decimals: DEFAULT_DECIMALS,
+            tick_size: DEFAULT_TICK_SIZE,
+            lot_size: 1,
+            currency: None,
+        }
+    }
+
+    /// Unique name of the instrument, e.g. `stock/shares/TQBR/GAZP`.
+    pub fn key(&self) -> String {
+        format!("{}/{}/{}/{}", self.board.engine, self.board.market, self.board.board, self.ticker)
+    }
+}
+
+impl From<SecurityDescription> for Instrument {
+    fn from(d: SecurityDescription) -> Instrument {
+        Instrument {
+            ticker: d.secid,
+            board: d.board,
+            decimals: d.decimals,
+            tick_size: d.min_step,
+            lot_size: d.lot_size,
+            currency: d.currency,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Instrument {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ticker: &str, engine: &str, market: &str, board: &str) -> Instrument {
+        Instrument::with_board(ticker, Board::new(engine, market, board))
+    }
+
+    pub fn from_description(description: &SecurityDescription) -> Instrument {
+        Instrument::from(description.clone())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn engine(&self) -> String {
+        self.board.engine.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn market(&self) -> String {
+        self.board.market.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn board_id(&self) -> String {
+        self.board.board.clone()
+    }
+
+    /// Price rounded to the nearest tick.
+    pub fn snap_price(&self, price: f64) -> f64 {
+        if self.tick_size > 0.0 {
+            (price / self.tick_size).round() * self.tick_size
+        } else {
+            price
+        }
+    }
+
+    /// Price snapped to the tick and shown with the instrument's decimals.
+    pub fn format_price(&self, price: f64) -> String {
+        format!("{:.*}", self.decimals as usize, self.snap_price(price))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices_follow_tick_and_decimals() {
+        let vtbr = Instrument { decimals: 6, tick_size: 0.000005, ..Instrument::with_board("VTBR", Board::shares()) };
+        assert_eq!(vtbr.format_price(0.0241234), "0.024125");
+        assert_eq!(vtbr.key(), "stock/shares/TQBR/VTBR");
+
+        let si = Instrument { decimals: 0, tick_size: 1.0, ..Instrument::with_board("SiH3", Board::futures()) };
+        assert_eq!(si.snap_price(75123.6), 75124.0);
+        assert_eq!(si.format_price(75123.4), "75123");
+
+        assert_eq!(Instrument::with_board("GAZP", Board::shares()).format_price(165.237), "165.24");
+    }
+}
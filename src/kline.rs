@@ -0,0 +1,600 @@
+use std::{ collections::HashMap, fmt };
+use serde::{ Serialize, Deserialize };
+use serde_json::Value;
+use wasm_bindgen::JsValue;
+use chrono::{ DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Utc };
+use crate::calendar;
+use crate::http::{ HttpClient, HttpError, percent_encode };
+use crate::instrument::Instrument;
+use crate::moex::{ MoexColumns, CANDLE_COLUMNS, candle_interval };
+use crate::source::{ DataSource, SourceFuture };
+use crate::chart::{
+    Period, TradeInterval,
+    tradedata::{
+        TradeExtras, TradeItem, TradeData,
+    }
+};
+
+/// Pages requested at most for one fetch, against an API that never says it is done.
+pub(crate) const MAX_PAGES: usize = 1000;
+
+/// Field of a bar: a position within an array row or a name. Array rows are looked up
+/// by name through the `columns` of the response, object rows by their keys.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KlineField {
+    Index(usize),
+    Name(String),
+}
+
+impl From<&str> for KlineField {
+    fn from(name: &str) -> KlineField {
+        KlineField::Name(name.to_string())
+    }
+}
+
+/// How times are written in the responses and in the query.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    UnixSeconds,
+    UnixMillis,
+    Rfc3339,
+    /// chrono format of a local date and time or of a bare date, e.g. `%Y-%m-%d %H:%M:%S`.
+    Pattern(String),
+}
+
+/// How the rest of a long history is asked for.
+#[derive(Debug)]
+#[derive(Clone, Default)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Pagination {
+    /// Everything comes in one response.
+    #[default]
+    Single,
+    /// The query parameter `param` holds the number of rows to skip. The pages end with an empty one
+    /// or, when `page_size` is known, with a shorter one.
+    Offset { param: String, #[serde(default)] page_size: usize },
+    /// The next page starts just after the last bar received, `{from}` is moved on.
+    Time,
+    /// The response field at `path` holds a token sent in `param` for the next page, absent or null on the last one.
+    Token { param: String, path: Vec<String> },
+}
+
+/// Where the bars are in the JSON of a vendor and how it is asked for them.
+/// The `url` may hold `{ticker}`, `{engine}`, `{market}`, `{board}`, `{interval}`, `{from}` and `{till}`.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub struct KlineMapping {
+    pub url: String,
+    /// Names of the intervals in the query, `1m`, `10m`, `1h`, `1d`, `1w` and `1M` unless given.
+    #[serde(default)]
+    pub intervals: HashMap<TradeInterval, String>,
+    /// Keys leading to the array of rows, empty when the response is the array.
+    #[serde(default)]
+    pub rows: Vec<String>,
+    /// Keys leading to the column names of array rows, like the `columns` of an ISS table.
+    #[serde(default)]
+    pub columns: Option<Vec<String>>,
+    pub date: KlineField,
+    pub open: KlineField,
+    pub high: KlineField,
+    pub low: KlineField,
+    pub close: KlineField,
+    pub volume: KlineField,
+    /// Turnover of the bar, shown by the chart when the vendor reports it.
+    #[serde(default)]
+    pub value: Option<KlineField>,
+    /// Weighted average price, number of trades and legal close of the bar, see `TradeExtras`.
+    #[serde(default)]
+    pub waprice: Option<KlineField>,
+    #[serde(default)]
+    pub num_trades: Option<KlineField>,
+    #[serde(default)]
+    pub legal_close: Option<KlineField>,
+    pub time_format: TimeFormat,
+    /// Format of `{from}` and `{till}`, the one of the responses unless given.
+    #[serde(default)]
+    pub query_time_format: Option<TimeFormat>,
+    /// Offset of the clock pattern times are on, in seconds east of UTC.
+    #[serde(default)]
+    pub utc_offset: i32,
+    /// Pattern times are on the MOEX clock with its history, see `calendar::Moscow`, instead of at `utc_offset`.
+    #[serde(default)]
+    pub exchange_clock: bool,
+    #[serde(default)]
+    pub pagination: Pagination,
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum KlineError {
+    Http(HttpError),
+    /// The body is not JSON or has no rows where the mapping expects them.
+    Decode(String),
+    /// The response has no field of the mapping.
+    MissingField(String),
+    /// A field of the row is missing or has an unexpected type.
+    BadRow { row: usize, field: String },
+}
+
+impl fmt::Display for KlineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Http(e)               => write!(f, "{}", e),
+            Self::Decode(e)             => write!(f, "cannot decode kline response: {}", e),
+            Self::MissingField(name)    => write!(f, "kline response has no field {}", name),
+            Self::BadRow { row, field } => write!(f, "bad value in field {} of row {}", field, row),
+        }
+    }
+}
+
+impl std::error::Error for KlineError {}
+
+impl From<HttpError> for KlineError {
+    fn from(e: HttpError) -> KlineError {
+        KlineError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for KlineError {
+    fn from(e: serde_json::Error) -> KlineError {
+        KlineError::Decode(e.to_string())
+    }
+}
+
+impl From<KlineError> for JsValue {
+    fn from(e: KlineError) -> JsValue {
+        JsValue::from_str(&e.to_string())
+    }
+}
+
+fn field_name(field: &KlineField) -> String {
+    match field {
+        KlineField::Index(idx) => idx.to_string(),
+        KlineField::Name(name) => name.clone(),
+    }
+}
+
+/// Value at the end of the `path` of keys.
+fn at_path<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+/// Number of a JSON number or of a string holding one, vendors quote prices to keep their precision.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+impl KlineMapping {
+    /// Mapping from a JSON configuration, see the fields of `KlineMapping`.
+    pub fn from_json(text: &str) -> Result<KlineMapping, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    /// Rows of the ISS `table` in its `columns` and `data` layout, the one of `MoexHistory`, with
+    /// the bars in `columns` and times written by `time_format` on the exchange clock.
+    /// The URL is left empty, `Moex` asks for the pages itself.
+    pub fn iss(table: &str, columns: &MoexColumns, time_format: &str) -> KlineMapping {
+        let optional = |name: Option<&str>| name.map(KlineField::from);
+        KlineMapping {
+            url: String::new(),
+            intervals: HashMap::new(),
+            rows: vec![table.to_string(), String::from("data")],
+            columns: Some(vec![table.to_string(), String::from("columns")]),
+            date: KlineField::from(columns.date),
+            open: KlineField::from(columns.open),
+            high: KlineField::from(columns.high),
+            low: KlineField::from(columns.low),
+            close: KlineField::from(columns.close),
+            volume: KlineField::from(columns.volume),
+            value: optional(columns.value),
+            waprice: optional(columns.waprice),
+            num_trades: optional(columns.num_trades),
+            legal_close: optional(columns.legal_close),
+            time_format: TimeFormat::Pattern(time_format.to_string()),
+            query_time_format: None,
+            utc_offset: 0,
+            exchange_clock: true,
+            pagination: Pagination::Single,
+        }
+    }
+
+    /// ISS candles, paged by `start`.
+    pub fn moex_candles(base_url: &str) -> KlineMapping {
+        let intervals: HashMap<TradeInterval, String> =
+            [TradeInterval::Minute1, TradeInterval::Minute10, TradeInterval::Hour, TradeInterval::Day, TradeInterval::Week, TradeInterval::Month]
+                .iter()
+                .map(|interval| (*interval, candle_interval(*interval).to_string()))
+                .collect();
+        KlineMapping {
+            url: format!("{}/iss/engines/{{engine}}/markets/{{market}}/boards/{{board}}/securities/{{ticker}}/candles.json?from={{from}}&till={{till}}&interval={{interval}}",
+                base_url.trim_end_matches('/')),
+            intervals,
+            pagination: Pagination::Offset { param: String::from("start"), page_size: 0 },
+            ..KlineMapping::iss("candles", &CANDLE_COLUMNS, "%Y-%m-%d %H:%M:%S")
+        }
+    }
+
+    /// Date and time of `d` on the clock of pattern times.
+    fn local(&self, d: &DateTime<Utc>) -> NaiveDateTime {
+        if self.exchange_clock {
+            calendar::exchange_local(d)
+        } else {
+            d.naive_utc() + Duration::seconds(self.utc_offset as i64)
+        }
+    }
+
+    /// Moment a date and time read on the clock of pattern times is.
+    fn moment(&self, naive: NaiveDateTime) -> DateTime<Utc> {
+        if self.exchange_clock {
+            calendar::exchange_datetime(naive)
+        } else {
+            (naive - Duration::seconds(self.utc_offset as i64)).and_utc()
+        }
+    }
+
+    fn interval_name(&self, interval: TradeInterval) -> String {
+        if let Some(name) = self.intervals.get(&interval) {
+            return name.clone();
+        }
+        let name: &str =
+            match interval {
+                TradeInterval::Minute1  => "1m",
+                TradeInterval::Minute10 => "10m",
+                TradeInterval::Hour     => "1h",
+                TradeInterval::Day      => "1d",
+                TradeInterval::Week     => "1w",
+                TradeInterval::Month    => "1M",
+            };
+        name.to_string()
+    }
+
+    fn format_time(&self, d: &DateTime<Utc>) -> String {
+        match self.query_time_format.as_ref().unwrap_or(&self.time_format) {
+            TimeFormat::UnixSeconds => d.timestamp().to_string(),
+            TimeFormat::UnixMillis  => d.timestamp_millis().to_string(),
+            TimeFormat::Rfc3339     => d.to_rfc3339_opts(SecondsFormat::Secs, true),
+            TimeFormat::Pattern(pattern) => self.local(d).format(pattern).to_string(),
+        }
+    }
+
+    fn parse_time(&self, value: &Value) -> Option<DateTime<Utc>> {
+        match &self.time_format {
+            TimeFormat::UnixSeconds => DateTime::from_timestamp(number(value)? as i64, 0),
+            TimeFormat::UnixMillis  => DateTime::from_timestamp_millis(number(value)? as i64),
+            TimeFormat::Rfc3339     => DateTime::parse_from_rfc3339(value.as_str()?).ok().map(|d| d.with_timezone(&Utc)),
+            TimeFormat::Pattern(pattern) => {
+                let text: &str = value.as_str()?;
+                let naive: NaiveDateTime = NaiveDateTime::parse_from_str(text, pattern)
+                    .or_else(|_| NaiveDate::parse_from_str(text, pattern).map(|d| NaiveDateTime::new(d, NaiveTime::default())))
+                    .ok()?;
+                Some(self.moment(naive))
+            },
+        }
+    }
+
+    /// URL of the first page for the instrument, interval and period. The instrument is percent-encoded.
+    pub fn request_url(&self, instrument: &Instrument, interval: TradeInterval, period: &Period<Utc>) -> String {
+        self.url
+            .replace("{ticker}", &percent_encode(&instrument.ticker))
+            .replace("{engine}", &percent_encode(&instrument.board.engine))
+            .replace("{market}", &percent_encode(&instrument.board.market))
+            .replace("{board}", &percent_encode(&instrument.board.board))
+            .replace("{interval}", &self.interval_name(interval))
+            .replace("{from}", &self.format_time(period.begin()))
+            .replace("{till}", &self.format_time(period.end()))
+    }
+
+    /// Appends the bars of a response to `trade_data` and returns the number of rows read.
+    /// `first_row` is the number of the page's first row within the whole history, used in errors.
+    /// Rows without trades become gap bars at the previous close, see `TradeItem::traded`;
+    /// leading ones have nothing to carry and are dropped.
+    pub fn add_rows(&self, response: &Value, first_row: usize, trade_data: &mut TradeData) -> Result<usize, KlineError> {
+        let rows: &Vec<Value> = at_path(response, &self.rows)
+            .and_then(Value::as_array)
+            .ok_or_else(|| KlineError::Decode(format!("no array of rows at {:?}", self.rows)))?;
+        let columns: Option<Vec<&str>> =
+            match &self.columns {
+                Some(path) => Some(
+                    at_path(response, path)
+                        .and_then(Value::as_array)
+                        .ok_or_else(|| KlineError::Decode(format!("no column names at {:?}", path)))?
+                        .iter()
+                        .map(|c| c.as_str().unwrap_or_default())
+                        .collect()
+                ),
+                None => None,
+            };
+        let position = |field: &KlineField| -> Result<Option<usize>, KlineError> {
+            match (field, &columns) {
+                (KlineField::Index(idx), _) => Ok(Some(*idx)),
+                (KlineField::Name(name), Some(columns)) => columns.iter()
+                    .position(|c| c.eq_ignore_ascii_case(name))
+                    .map(Some)
+                    .ok_or_else(|| KlineError::MissingField(name.clone())),
+                (KlineField::Name(_), None) => Ok(None),
+            }
+        };
+        let fields: [&KlineField; 6] = [&self.date, &self.high, &self.low, &self.open, &self.close, &self.volume];
+        let positions: Vec<Option<usize>> = fields.iter().map(|field| position(field)).collect::<Result<_, _>>()?;
+        // the extras are informational, a response without them gives bars without them
+        let extra_fields: [Option<&KlineField>; 4] = [self.waprice.as_ref(), self.value.as_ref(), self.num_trades.as_ref(), self.legal_close.as_ref()];
+        let extra_positions: Vec<Option<usize>> = extra_fields.iter()
+            .map(|field| field.and_then(|field| position(field).ok().flatten()))
+            .collect();
+
+        for (idx, row) in rows.iter().enumerate() {
+            let row_number: usize = first_row + idx;
+            let bad = |field: &KlineField| KlineError::BadRow { row: row_number, field: field_name(field) };
+            let cell = |i: usize| -> Option<&Value> {
+                match (positions[i], fields[i]) {
+                    (Some(pos), _) => row.get(pos),
+                    (None, KlineField::Name(name)) => row.get(name),
+                    (None, KlineField::Index(_)) => None,
+                }
+            };
+            // `None` for a null value, an error for a missing or non-numeric one
+            let value = |i: usize| -> Result<Option<f64>, KlineError> {
+                match cell(i) {
+                    Some(Value::Null) => Ok(None),
+                    value => value.and_then(number).map(Some).ok_or_else(|| bad(fields[i])),
+                }
+            };
+
+            let date: DateTime<Utc> = cell(0).and_then(|value| self.parse_time(value)).ok_or_else(|| bad(fields[0]))?;
+            let volume: Option<f64> = value(5)?;
+            let extra = |i: usize| -> Option<f64> {
+                match (extra_positions[i], extra_fields[i]) {
+                    (Some(pos), _) => row.get(pos),
+                    (None, Some(KlineField::Name(name))) => row.get(name),
+                    _ => None,
+                }
+                .and_then(number)
+            };
+            let extras: TradeExtras = TradeExtras {
+                waprice: extra(0),
+                value: extra(1),
+                num_trades: extra(2).map(|v| v as u32),
+                legal_close: extra(3),
+            };
+            match TradeItem::traded(date, value(1)?, value(2)?, value(3)?, value(4)?, volume) {
+                Some(item) => trade_data.add_item(item.with_extras(extras)),
+                None => {
+                    if let Some(close) = trade_data.last().map(|item| item.hlocv().c) {
+                        trade_data.add_item(TradeItem::gap(date, close).with_extras(extras));
+                    }
+                },
+            }
+        }
+        Ok(rows.len())
+    }
+}
+
+/// Bars from a REST API described by a `KlineMapping`, so a vendor is added by configuration.
+pub struct JsonSource {
+    mapping: KlineMapping,
+    client: HttpClient,
+}
+
+impl JsonSource {
+    pub fn new(mapping: KlineMapping) -> JsonSource {
+        JsonSource { mapping, client: HttpClient::new() }
+    }
+
+    pub fn with_client(self, client: HttpClient) -> JsonSource {
+        JsonSource { client, ..self }
+    }
+
+    pub fn mapping(&self) -> &KlineMapping {
+        &self.mapping
+    }
+
+    async fn get(&self, url: &str) -> Result<Value, KlineError> {
+        let text: String = self.client.get_text(url).await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// All the pages of the history of the instrument within the period.
+    pub async fn request_data(&self, instrument: &Instrument, interval: TradeInterval, period: &Period<Utc>) -> Result<TradeData, KlineError> {
+        let mut trade_data: TradeData = TradeData::new(interval);
+        trade_data.set_instrument(instrument.clone());
+        let mut url: String = self.mapping.request_url(instrument, interval, period);
+        let mut first_row: usize = 0;
+
+        for _ in 0..MAX_PAGES {
+            let response: Value = self.get(&url).await?;
+            let last: Option<DateTime<Utc>> = trade_data.last().map(|item| *item.date());
+            let rows: usize = self.mapping.add_rows(&response, first_row, &mut trade_data)?;
+            first_row += rows;
+            if rows == 0 {
+                break;
+            }
+
+            let next: Option<String> =
+                match &self.mapping.pagination {
+                    Pagination::Single => None,
+                    Pagination::Offset { param, page_size } => {
+                        if *page_size > 0 && rows < *page_size {
+                            None
+                        } else {
+                            Some(with_query_param(&url, param, &first_row.to_string()))
+                        }
+                    },
+                    Pagination::Time => {
+                        // a page adding nothing after the last bar means the history is exhausted
+                        match trade_data.last().map(|item| *item.date()) {
+                            Some(end) if Some(end) != last && end < *period.end() => {
+                                let step: Duration = match self.mapping.query_time_format.as_ref().unwrap_or(&self.mapping.time_format) {
+                                    TimeFormat::UnixMillis => Duration::milliseconds(1),
+                                    _ => Duration::seconds(1),
+                                };
+                                Some(self.mapping.request_url(instrument, interval, &Period::new(end + step, *period.end())))
+                            },
+                            _ => None,
+                        }
+                    },
+                    Pagination::Token { param, path } => {
+                        match at_path(&response, path) {
+                            Some(Value::String(token)) => Some(with_query_param(&url, param, token)),
+                            Some(Value::Number(token)) => Some(with_query_param(&url, param, &token.to_string())),
+                            _ => None,
+                        }
+                    },
+                };
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(trade_data)
+    }
+}
+
+/// `url` with the query parameter `name` set to `value`, percent-encoded, replacing an earlier one.
+fn with_query_param(url: &str, name: &str, value: &str) -> String {
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+    let mut params: Vec<String> = query.split('&')
+        .filter(|param| !param.is_empty() && param.split('=').next() != Some(name))
+        .map(String::from)
+        .collect();
+    params.push(format!("{}={}", name, percent_encode(value)));
+    format!("{}?{}", base, params.join("&"))
+}
+
+impl DataSource for JsonSource {
+    fn fetch<'a>(&'a self, instrument: &'a Instrument, interval: TradeInterval, period: &'a Period<Utc>) -> SourceFuture<'a> {
+        Box::pin(async move {
+            Ok(self.request_data(instrument, interval, period).await?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::moex::{ Board, Moex };
+    use crate::stand_in::{ StandIn, query_param };
+
+    fn period() -> Period<Utc> {
+        Period::new(Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap(), Utc.with_ymd_and_hms(2023, 3, 2, 0, 0, 0).unwrap())
+    }
+
+    fn gazp() -> Instrument {
+        Instrument::with_board("GAZP", Board::shares())
+    }
+
+    #[test]
+    fn encodes_url_parts() {
+        assert_eq!(with_query_param("https://vendor/bars?from=1&page=a", "page", "n&x=1/2"), "https://vendor/bars?from=1&page=n%26x%3D1%2F2");
+        let mapping = KlineMapping { url: String::from("https://vendor/bars/{ticker}?interval={interval}"), ..KlineMapping::iss("bars", &CANDLE_COLUMNS, "%Y-%m-%d") };
+        let instrument = Instrument::with_board("BRK/A B", Board::shares());
+        assert_eq!(mapping.request_url(&instrument, TradeInterval::Hour, &period()), "https://vendor/bars/BRK%2FA%20B?interval=1h");
+    }
+
+    #[tokio::test]
+    async fn moex_candles_as_mapping() {
+        let server = StandIn::serve(|target| {
+            let start: usize = query_param(target, "start").and_then(|s| s.parse().ok()).unwrap_or(0);
+            let rows: Vec<String> = (start..120.min(start + 50))
+                .map(|i| format!(r#"[{}, {}, {}, {}, 1000.0, {}, "2023-03-01 {:02}:{:02}:00", "2023-03-01 {:02}:{:02}:59"]"#,
+                    100 + i, 101 + i, 102 + i, 99 + i, 1 + i, 10 + i / 60, i % 60, 10 + i / 60, i % 60))
+                .collect();
+            (200, format!(r#"{{"candles": {{"columns": ["open", "close", "high", "low", "value", "volume", "begin", "end"], "data": [{}]}}}}"#, rows.join(",")))
+        });
+
+        let source = JsonSource::new(KlineMapping::moex_candles(server.url()));
+        let trade_data = source.request_data(&gazp(), TradeInterval::Minute1, &period()).await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].starts_with("/iss/engines/stock/markets/shares/boards/TQBR/securities/GAZP/candles.json?from=2023-03-01%2003:00:00"));
+        assert_eq!(query_param(&requests[3], "start"), Some("120"));
+
+        // the same bars as the hand written ISS client reads
+        let from = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap().and_hms_opt(3, 0, 0).unwrap();
+        let till = NaiveDate::from_ymd_opt(2023, 3, 2).unwrap().and_hms_opt(3, 0, 0).unwrap();
+        let moex_data = Moex::with_base_url(server.url()).request_data(&gazp(), TradeInterval::Minute1, from, till).await.unwrap();
+        assert_eq!(trade_data.len(), 120);
+        assert_eq!(serde_json::to_string(&trade_data).unwrap(), serde_json::to_string(&moex_data).unwrap());
+    }
+
+    #[tokio::test]
+    async fn arrays_paged_by_time() {
+        // bars as arrays of quoted numbers, at most two a page from `startTime` on
+        let server = StandIn::serve(|target| {
+            let start: i64 = query_param(target, "startTime").and_then(|s| s.parse().ok()).unwrap_or(0);
+            let rows: Vec<String> = (0..5i64)
+                .map(|i| 1677628800000 + i * 3600000)
+                .filter(|time| *time >= start)
+                .take(2)
+                .map(|time| format!(r#"[{}, "10.5", "11.0", "10.0", "10.8", "1500.25", {}]"#, time, time + 3599999))
+                .collect();
+            (200, format!("[{}]", rows.join(",")))
+        });
+        let mapping = KlineMapping::from_json(&format!(r#"{{
+            "url": "{}/api/v3/klines?symbol={{ticker}}&interval={{interval}}&startTime={{from}}&endTime={{till}}",
+            "date": 0, "open": 1, "high": 2, "low": 3, "close": 4, "volume": 5,
+            "time_format": "unix_millis",
+            "pagination": {{"type": "time"}}
+        }}"#, server.url())).unwrap();
+
+        let trade_data = JsonSource::new(mapping).request_data(&Instrument::with_board("BTCUSDT", Board::default()), TradeInterval::Hour, &period()).await.unwrap();
+        assert_eq!(trade_data.len(), 5);
+        assert_eq!(*trade_data.item(4).unwrap().date(), Utc.with_ymd_and_hms(2023, 3, 1, 4, 0, 0).unwrap());
+        assert_eq!(trade_data.item(0).unwrap().hlocv().v, 1500.25);
+        let requests = server.requests();
+        // the last page is empty
+        assert_eq!(requests.len(), 4);
+        assert_eq!(query_param(&requests[0], "interval"), Some("1h"));
+        assert_eq!(query_param(&requests[1], "startTime"), Some("1677632400001"));
+    }
+
+    #[tokio::test]
+    async fn objects_paged_by_token() {
+        let server = StandIn::serve(|target| {
+            if query_param(target, "page") == Some("2") {
+                (200, String::from(r#"{"result": {"bars": [{"t": "2023-03-02T00:00:00Z", "o": null, "h": 3, "l": 1, "c": 2.5, "v": null}], "next": null}}"#))
+            } else {
+                (200, String::from(r#"{"result": {"bars": [{"t": "2023-03-01T00:00:00Z", "o": 1, "h": 2, "l": 1, "c": 2, "v": 7},
+                    {"t": "2023-03-01T12:00:00Z", "o": null, "h": null, "l": null, "c": null, "v": 0}], "next": 2}}"#))
+            }
+        });
+        let mut mapping = KlineMapping::from_json(r#"{
+            "url": "", "rows": ["result", "bars"],
+            "date": "t", "open": "o", "high": "h", "low": "l", "close": "c", "volume": "v",
+            "time_format": "rfc3339",
+            "pagination": {"type": "token", "param": "page", "path": ["result", "next"]}
+        }"#).unwrap();
+        mapping.url = format!("{}/bars/{{ticker}}?from={{from}}", server.url());
+
+        let trade_data = JsonSource::new(mapping.clone()).request_data(&gazp(), TradeInterval::Day, &period()).await.unwrap();
+        assert_eq!(server.requests()[0], "/bars/GAZP?from=2023-03-01T00:00:00Z");
+        assert_eq!(server.requests()[1], "/bars/GAZP?from=2023-03-01T00:00:00Z&page=2");
+        let gaps: Vec<bool> = trade_data.iter_data().map(|item| item.is_gap()).collect();
+        assert_eq!(gaps, [false, true, false]);
+        assert_eq!(trade_data.item(2).unwrap().hlocv().v, 0.0);
+        // the missing open of a traded bar is taken from its close
+        assert_eq!(trade_data.item(2).unwrap().hlocv().o, 2.5);
+
+        let mut bad = TradeData::new(TradeInterval::Day);
+        let response: Value = serde_json::from_str(r#"{"result": {"bars": [{"t": "yesterday", "o": 1, "h": 2, "l": 1, "c": 2, "v": 7}]}}"#).unwrap();
+        assert_eq!(mapping.add_rows(&response, 0, &mut bad), Err(KlineError::BadRow { row: 0, field: String::from("t") }));
+        let response: Value = serde_json::from_str(r#"{"bars": []}"#).unwrap();
+        assert!(matches!(mapping.add_rows(&response, 0, &mut bad), Err(KlineError::Decode(_))));
+    }
+}
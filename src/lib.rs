@@ -1,6 +1,18 @@
 // mod utils;
-mod chart;
-mod moex;
+pub mod cache;
+pub mod calendar;
+pub mod catalog;
+pub mod chart;
+pub mod corporate;
+pub mod csv;
+pub mod http;
+pub mod instrument;
+pub mod kline;
+pub mod live;
+pub mod moex;
+pub mod source;
+#[cfg(test)]
+mod stand_in;
 
 // use wasm_bindgen::prelude::*;
 
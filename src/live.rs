@@ -0,0 +1,164 @@
+use std::error::Error;
+use chrono::{ DateTime, Duration, Utc };
+use crate::calendar::TradingCalendar;
+use crate::instrument::Instrument;
+use crate::source::{ DataSource, Quote };
+use crate::chart::{
+    TradeInterval,
+    tradedata::{
+        TradeItem, TradeData,
+    }
+};
+
+/// State of the live mode of a chart: how often to poll and when it was done last.
+pub struct LiveMode {
+    interval: Duration,
+    last_poll: Option<DateTime<Utc>>,
+}
+
+impl LiveMode {
+    pub fn new(interval_ms: u32) -> LiveMode {
+        LiveMode {
+            interval: Duration::milliseconds(interval_ms as i64),
+            last_poll: None,
+        }
+    }
+    pub fn interval_ms(&self) -> u32 {
+        self.interval.num_milliseconds() as u32
+    }
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.last_poll.map(|last| now - last >= self.interval).unwrap_or(true)
+    }
+    pub fn polled(&mut self, now: DateTime<Utc>) {
+        self.last_poll = Some(now);
+    }
+}
+
+/// Bar of `interval` the quote falls into, merged with the last bar of `trade_data` if it is the same one.
+/// Daily bars take the day aggregates of the quote, the others are only moved by the last price.
+pub fn quote_bar(quote: &Quote, trade_data: &TradeData) -> TradeItem {
+    let interval: TradeInterval = *trade_data._interval();
+    let start: DateTime<Utc> = interval.bar_start(quote.time);
+    let day: bool = interval == TradeInterval::Day;
+
+    // a gap bar has no trades to merge with, the quote starts the bar anew
+    match trade_data.last().filter(|item| !item.is_gap() && interval.bar_start(*item.date()) == start) {
+        Some(item) => {
+            let hlocv = item.hlocv();
+            TradeItem::new(
+                *item.date(),
+                quote.high.filter(|_| day).unwrap_or(hlocv.h).max(quote.last),
+                quote.low.filter(|_| day).unwrap_or(hlocv.l).min(quote.last),
+                hlocv.o,
+                quote.last,
+                quote.volume.filter(|_| day).unwrap_or(hlocv.v),
+            )
+        },
+        None if day => TradeItem::new(
+            start,
+            quote.high.unwrap_or(quote.last).max(quote.last),
+            quote.low.unwrap_or(quote.last).min(quote.last),
+            quote.open.unwrap_or(quote.last),
+            quote.last,
+            quote.volume.unwrap_or(0.0),
+        ),
+        None => TradeItem::new(start, quote.last, quote.last, quote.last, quote.last, 0.0),
+    }
+}
+
+/// Asks the source for the quote of `instrument` and puts it into the last bar.
+/// Returns the index of the first changed item as `TradeData::update_tail` does, `None` without a quote
+/// or outside the sessions of `calendar`.
+pub async fn poll(source: &dyn DataSource, instrument: &Instrument, calendar: &TradingCalendar, trade_data: &mut TradeData) -> Result<Option<usize>, Box<dyn Error>> {
+    let quote: Quote =
+        match source.quote(instrument).await? {
+            Some(quote) => quote,
+            None => return Ok(None),
+        };
+    // the quote is dated when it was taken, while the exchange is closed its last price is an old one
+    if !calendar.is_open(&quote.time) {
+        return Ok(None);
+    }
+    let mut fresh: TradeData = TradeData::new(*trade_data._interval());
+    fresh.add_item(quote_bar(&quote, trade_data));
+    Ok(Some(trade_data.update_tail(fresh)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{ NaiveDate, TimeZone };
+    use crate::calendar;
+    use crate::moex::{ Board, Moex };
+    use crate::stand_in::StandIn;
+
+    const MARKETDATA: &str = r#"{"marketdata": {"columns": ["SECID", "LAST", "OPEN", "HIGH", "LOW", "VOLTODAY", "SYSTIME"],
+        "data": [["GAZP", 165.2, 163.0, 166.0, 162.5, 12345678, "2023-03-02 15:30:00"]]}}"#;
+
+    fn day_bars() -> TradeData {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
+        trade_data.add_item(TradeItem::new(calendar::exchange_midnight(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap()), 164.0, 160.0, 161.0, 163.0, 9000000.0));
+        trade_data
+    }
+
+    #[test]
+    fn live_mode_due() {
+        let now: DateTime<Utc> = Utc.with_ymd_and_hms(2023, 3, 2, 15, 30, 0).unwrap();
+        let mut live = LiveMode::new(10000);
+        assert!(live.is_due(now));
+        live.polled(now);
+        assert!(!live.is_due(now + Duration::seconds(5)));
+        assert!(live.is_due(now + Duration::seconds(10)));
+    }
+
+    #[test]
+    fn quote_updates_intraday_bar() {
+        let mut trade_data: TradeData = TradeData::new(TradeInterval::Hour);
+        trade_data.add_item(TradeItem::new(Utc.with_ymd_and_hms(2023, 3, 2, 15, 0, 0).unwrap(), 165.0, 164.0, 164.5, 164.8, 1000.0));
+        let quote = Quote { time: Utc.with_ymd_and_hms(2023, 3, 2, 15, 42, 0).unwrap(), last: 165.5, open: Some(163.0), high: Some(166.0), low: Some(162.5), volume: Some(1e7) };
+
+        let bar = quote_bar(&quote, &trade_data);
+        assert_eq!(bar.date(), trade_data.item(0).unwrap().date());
+        assert_eq!((bar.hlocv().h, bar.hlocv().l, bar.hlocv().o, bar.hlocv().c, bar.hlocv().v), (165.5, 164.0, 164.5, 165.5, 1000.0));
+
+        let quote = Quote { time: Utc.with_ymd_and_hms(2023, 3, 2, 16, 1, 0).unwrap(), ..quote };
+        let bar = quote_bar(&quote, &trade_data);
+        assert_eq!(*bar.date(), Utc.with_ymd_and_hms(2023, 3, 2, 16, 0, 0).unwrap());
+        assert_eq!(bar.hlocv().o, 165.5);
+    }
+
+    #[tokio::test]
+    async fn poll_marketdata() {
+        let server = StandIn::serve(|_| (200, String::from(MARKETDATA)));
+        let moex = Moex::with_base_url(server.url());
+        let mut trade_data: TradeData = day_bars();
+        let gazp = Instrument::with_board("GAZP", Board::shares());
+        let shares: TradingCalendar = TradingCalendar::moex();
+
+        // a new trading day is appended
+        assert_eq!(poll(&moex, &gazp, &shares, &mut trade_data).await.unwrap(), Some(1));
+        assert_eq!(trade_data.len(), 2);
+        let today = &trade_data.item(1).unwrap();
+        // the quote time is on the exchange clock and the day starts at Moscow midnight
+        assert_eq!(*today.date(), calendar::exchange_midnight(NaiveDate::from_ymd_opt(2023, 3, 2).unwrap()));
+        assert_eq!((today.hlocv().h, today.hlocv().l, today.hlocv().o, today.hlocv().c, today.hlocv().v), (166.0, 162.5, 163.0, 165.2, 12345678.0));
+
+        // and updated in place on the next poll
+        assert_eq!(poll(&moex, &gazp, &shares, &mut trade_data).await.unwrap(), Some(1));
+        assert_eq!(trade_data.len(), 2);
+
+        let request: &str = &server.requests()[0];
+        assert!(request.starts_with("/iss/engines/stock/markets/shares/boards/TQBR/securities/GAZP.json?iss.only=marketdata"));
+
+        let server = StandIn::serve(|_| (200, String::from(r#"{"marketdata": {"columns": ["SECID", "LAST", "OPEN", "HIGH", "LOW", "VOLTODAY", "SYSTIME"],
+            "data": [["GAZP", null, null, null, null, 0, "2023-03-02 09:50:00"]]}}"#)));
+        let moex = Moex::with_base_url(server.url());
+        assert_eq!(poll(&moex, &gazp, &shares, &mut trade_data).await.unwrap(), None);
+
+        // a Saturday snapshot repeats the last price of Friday
+        let server = StandIn::serve(|_| (200, MARKETDATA.replace("2023-03-02 15:30:00", "2023-03-04 12:00:00")));
+        let moex = Moex::with_base_url(server.url());
+        assert_eq!(poll(&moex, &gazp, &shares, &mut trade_data).await.unwrap(), None);
+        assert_eq!(trade_data.len(), 2);
+    }
+}
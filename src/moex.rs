@@ -1,10 +1,16 @@
-use serde::{ Serialize, Deserialize };
+use std::{ fmt, rc::Rc };
+use serde::{ Serialize, Deserialize, de::DeserializeOwned };
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
 use chrono::{ NaiveDateTime, NaiveDate, NaiveTime, DateTime, Utc };
+use crate::calendar;
+use crate::http::{ HttpClient, HttpError, TokenBucket, percent_encode };
+use crate::instrument::Instrument;
+use crate::kline::{ KlineError, KlineMapping, MAX_PAGES };
+use crate::source::{ DataSource, SourceFuture, Quote, QuoteFuture };
 use crate::chart::{
-    TradeInterval,
-    tradedata::{
-        TradeItem, TradeData,
-    }
+    Period, TradeInterval,
+    tradedata::TradeData,
 };
 
 #[derive(Debug)]
@@ -13,7 +19,7 @@ use crate::chart::{
 pub enum MoexValue{
     String(String),
     Int(i32),
-    Float(f32),
+    Float(f64),
     Null,
 }
 
@@ -26,94 +32,524 @@ pub struct MoexHistory {
 
 #[derive(Debug)]
 #[derive(Serialize,Deserialize)]
-pub struct MoexResponse {
-    pub history: MoexHistory,
+pub struct MoexMarketDataResponse {
+    pub marketdata: MoexHistory,
+}
+
+/// Names of the columns holding a bar in an ISS table.
+/// The extra columns are optional, a table without them gives bars without extras.
+pub struct MoexColumns {
+    pub date: &'static str,
+    pub high: &'static str,
+    pub low: &'static str,
+    pub open: &'static str,
+    pub close: &'static str,
+    pub volume: &'static str,
+    pub waprice: Option<&'static str>,
+    pub value: Option<&'static str>,
+    pub num_trades: Option<&'static str>,
+    pub legal_close: Option<&'static str>,
 }
 
+pub const HISTORY_COLUMNS: MoexColumns = MoexColumns {
+    date: "TRADEDATE",
+    high: "HIGH",
+    low: "LOW",
+    open: "OPEN",
+    close: "CLOSE",
+    volume: "VOLUME",
+    waprice: Some("WAPRICE"),
+    value: Some("VALUE"),
+    num_trades: Some("NUMTRADES"),
+    legal_close: Some("LEGALCLOSEPRICE"),
+};
+
+/// Currency boards report the traded volume in roubles only.
+pub const CURRENCY_HISTORY_COLUMNS: MoexColumns = MoexColumns {
+    date: "TRADEDATE",
+    high: "HIGH",
+    low: "LOW",
+    open: "OPEN",
+    close: "CLOSE",
+    volume: "VOLRUR",
+    waprice: Some("WAPRICE"),
+    value: Some("VOLRUR"),
+    num_trades: Some("NUMTRADES"),
+    legal_close: None,
+};
+
+pub const CANDLE_COLUMNS: MoexColumns = MoexColumns {
+    date: "begin",
+    high: "high",
+    low: "low",
+    open: "open",
+    close: "close",
+    volume: "volume",
+    waprice: None,
+    value: Some("value"),
+    num_trades: None,
+    legal_close: None,
+};
+
+pub struct MoexCursor {
+    pub index: usize,
+    pub total: usize,
+    pub page_size: usize,
+}
+
+impl MoexCursor {
+    pub fn from_history(cursor: &MoexHistory) -> Option<MoexCursor> {
+        let row: &Vec<MoexValue> = cursor.data.first()?;
+        let get = |name: &str| -> Option<usize> {
+            cursor.column(name)
+                .and_then(|pos| row.get(pos))
+                .and_then(get_value)
+                .map(|v| v as usize)
+        };
+        Some(
+            MoexCursor {
+                index: get("INDEX")?,
+                total: get("TOTAL")?,
+                page_size: get("PAGESIZE")?,
+            }
+        )
+    }
+    pub fn next_start(&self) -> Option<usize> {
+        let next: usize = self.index + self.page_size;
+        if self.page_size > 0 && next < self.total { Some(next) } else { None }
+    }
+}
+
+impl MoexHistory {
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+    /// Cell of the row in the column `name`, compared ignoring case.
+    pub fn cell<'a>(&self, row: &'a [MoexValue], name: &str) -> Option<&'a MoexValue> {
+        self.columns.iter()
+            .position(|c| c.eq_ignore_ascii_case(name))
+            .and_then(|pos| row.get(pos))
+    }
+}
+
+/// ISS dates and times are on the exchange clock, a bare date is the start of the exchange day.
 pub fn get_datetime(value: &MoexValue) -> Option<DateTime<Utc>> {
     if let MoexValue::String(datestring) = value {
-        NaiveDate::parse_from_str(datestring,"%Y-%m-%d")
-            .map(|d| NaiveDateTime::new(d, NaiveTime::default()))
-            .map(|d| DateTime::from_utc(d,Utc))
+        NaiveDateTime::parse_from_str(datestring,"%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDate::parse_from_str(datestring,"%Y-%m-%d").map(|d| NaiveDateTime::new(d, NaiveTime::default())))
+            .map(calendar::exchange_datetime)
             .ok()
     } else {
         None
     }
 }
 
-pub fn get_value(value: &MoexValue) -> Option<f32> {
+pub fn get_string(value: &MoexValue) -> Option<String> {
+    match value {
+        MoexValue::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+pub fn get_value(value: &MoexValue) -> Option<f64> {
     match value {
         MoexValue::String(_) |
         MoexValue::Null
             => None,
         MoexValue::Int(v)
-            => Some(*v as f32),
+            => Some(*v as f64),
         MoexValue::Float(v)
             => Some(*v),
     }
 }
 
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum MoexError {
+    /// The request did not reach ISS or the connection broke.
+    Network(String),
+    /// ISS answered with a non-success status code.
+    HttpStatus(u16),
+    /// ISS did not answer in time, also after the retries.
+    Timeout,
+    /// The body is not the JSON layout expected for the endpoint.
+    Decode(String),
+    /// The table has no column with this name.
+    MissingColumn(String),
+    /// A cell of the row is missing or has an unexpected type.
+    BadRow { row: usize, column: String },
+    /// The request succeeded but no bars were returned for the ticker.
+    NoData(String),
+    /// Pages kept coming after this many were requested.
+    TooManyPages(usize),
+}
+
+impl fmt::Display for MoexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Network(e)            => write!(f, "network error: {}", e),
+            Self::HttpStatus(status)    => write!(f, "ISS responded with HTTP status {}", status),
+            Self::Timeout               => write!(f, "ISS did not respond in time"),
+            Self::Decode(e)             => write!(f, "cannot decode ISS response: {}", e),
+            Self::MissingColumn(name)   => write!(f, "ISS response has no column {}", name),
+            Self::BadRow { row, column }
+                                        => write!(f, "bad value in column {} of row {}", column, row),
+            Self::NoData(ticker)        => write!(f, "no data for ticker {}", ticker),
+            Self::TooManyPages(pages)   => write!(f, "ISS kept paging after {} pages", pages),
+        }
+    }
+}
+
+impl std::error::Error for MoexError {}
+
+impl From<HttpError> for MoexError {
+    fn from(e: HttpError) -> MoexError {
+        match e {
+            HttpError::Network(e)       => MoexError::Network(e),
+            HttpError::Status(status)   => MoexError::HttpStatus(status),
+            HttpError::Timeout          => MoexError::Timeout,
+        }
+    }
+}
+
+impl From<KlineError> for MoexError {
+    fn from(e: KlineError) -> MoexError {
+        match e {
+            KlineError::Http(e)                 => MoexError::from(e),
+            KlineError::Decode(e)               => MoexError::Decode(e),
+            KlineError::MissingField(name)      => MoexError::MissingColumn(name),
+            KlineError::BadRow { row, field }   => MoexError::BadRow { row, column: field },
+        }
+    }
+}
+
+impl From<MoexError> for JsValue {
+    fn from(e: MoexError) -> JsValue {
+        JsValue::from_str(&e.to_string())
+    }
+}
+
+const ISS_URL: &str = "https://iss.moex.com";
+
+/// Where and how ISS is reached: the base URL (ISS itself, a CORS proxy or a local server),
+/// headers sent with every request, credentials and the retry settings of the client.
+#[wasm_bindgen]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct MoexConfig {
+    base_url: String,
+    headers: Vec<(String, String)>,
+    credentials: Option<(String, String)>,
+    token: Option<String>,
+    timeout_ms: Option<u32>,
+    retries: Option<u32>,
+    /// Rate limit of all the clients made from this configuration and its copies.
+    bucket: Rc<TokenBucket>,
+}
+
+impl Default for MoexConfig {
+    fn default() -> Self {
+        MoexConfig::with_base_url(ISS_URL)
+    }
+}
+
+impl MoexConfig {
+    pub fn with_base_url(base_url: &str) -> MoexConfig {
+        MoexConfig {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers: Vec::new(),
+            credentials: None,
+            token: None,
+            timeout_ms: None,
+            retries: None,
+            bucket: Rc::new(TokenBucket::default()),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Client sending the configured headers and credentials. The clients of a configuration
+    /// and of its clones share one rate limit.
+    pub fn client(&self) -> HttpClient {
+        let mut client = HttpClient::new();
+        client.share_rate_limit(self.bucket.clone());
+        for (name, value) in self.headers.iter() {
+            client.add_header(name, value);
+        }
+        if let Some((user, password)) = &self.credentials {
+            client.set_basic_auth(user, password);
+        }
+        if let Some(token) = &self.token {
+            client.set_bearer_token(token);
+        }
+        if let Some(timeout_ms) = self.timeout_ms {
+            client.set_timeout(chrono::Duration::milliseconds(timeout_ms as i64));
+        }
+        if let Some(retries) = self.retries {
+            client.set_retries(retries, chrono::Duration::milliseconds(500));
+        }
+        client
+    }
+}
+
+#[wasm_bindgen]
+impl MoexConfig {
+    pub fn new() -> MoexConfig {
+        MoexConfig::default()
+    }
+    /// E.g. `https://iss.moex.com`, a proxy adding CORS headers or `http://localhost:8080`.
+    pub fn set_base_url(&mut self, base_url: &str) {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+    }
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+    /// Login and password of a MOEX account, sent with basic authentication.
+    pub fn set_credentials(&mut self, user: &str, password: &str) {
+        self.credentials = Some((user.to_string(), password.to_string()));
+    }
+    /// API token, e.g. of AlgoPack, sent as a bearer token.
+    pub fn set_token(&mut self, token: &str) {
+        self.token = Some(token.to_string());
+    }
+    pub fn set_timeout_ms(&mut self, timeout_ms: u32) {
+        self.timeout_ms = Some(timeout_ms);
+    }
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = Some(retries);
+    }
+    /// Requests going to ISS at once at most, counted together for the copies made by `share` after it.
+    pub fn set_max_in_flight(&mut self, max_in_flight: u32) {
+        self.bucket = Rc::new(TokenBucket::default().with_max_in_flight(max_in_flight as usize));
+    }
+    /// Copy of the settings sharing the rate limit, for a chart and a catalog reaching the same ISS.
+    pub fn share(&self) -> MoexConfig {
+        self.clone()
+    }
+}
+
+/// Location of a security on the exchange: trading engine, market and board.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+pub struct Board {
+    pub engine: String,
+    pub market: String,
+    pub board: String,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board::shares()
+    }
+}
+
+impl Board {
+    pub fn new(engine: &str, market: &str, board: &str) -> Board {
+        Board {
+            engine: engine.to_string(),
+            market: market.to_string(),
+            board: board.to_string(),
+        }
+    }
+    /// Main board for shares, TQBR.
+    pub fn shares() -> Board {
+        Board::new("stock", "shares", "TQBR")
+    }
+    /// Main board for OFZ bonds, TQOB.
+    pub fn bonds() -> Board {
+        Board::new("stock", "bonds", "TQOB")
+    }
+    /// Currency pairs, CETS.
+    pub fn currency() -> Board {
+        Board::new("currency", "selt", "CETS")
+    }
+    /// FORTS futures, RFUD.
+    pub fn futures() -> Board {
+        Board::new("futures", "forts", "RFUD")
+    }
+    pub(crate) fn path(&self) -> String {
+        format!("engines/{}/markets/{}/boards/{}", percent_encode(&self.engine), percent_encode(&self.market), percent_encode(&self.board))
+    }
+    fn history_columns(&self) -> &'static MoexColumns {
+        match self.engine.as_str() {
+            "currency" => &CURRENCY_HISTORY_COLUMNS,
+            _ => &HISTORY_COLUMNS,
+        }
+    }
+}
+
 pub struct Moex {
+    base_url: String,
+    client: HttpClient,
+}
+
+impl Default for Moex {
+    fn default() -> Self {
+        Moex::new()
+    }
+}
 
+/// Value of the `interval` parameter of the ISS candles endpoint.
+pub fn candle_interval(interval: TradeInterval) -> u32 {
+    match interval {
+        TradeInterval::Minute1  => 1,
+        TradeInterval::Minute10 => 10,
+        TradeInterval::Hour     => 60,
+        TradeInterval::Day      => 24,
+        TradeInterval::Week     => 7,
+        TradeInterval::Month    => 31,
+    }
 }
 
 impl Moex {
-    pub async fn request_data(ticker: &str, from: NaiveDateTime) -> Result<TradeData, ()> {
-        let url = format!("http://iss.moex.com/iss/history/engines/stock/markets/shares/boards/tqbr/securities/{}.json?from={}", ticker, from.format("%Y-%m-%d").to_string());
-
-        let d: MoexResponse =
-            reqwest::get(url)
-                .await.unwrap()
-                .json()
-                .await.unwrap();
-    
-        let mut d_pos: Option<usize> = None;
-        let mut h_pos: Option<usize> = None;
-        let mut l_pos: Option<usize> = None;
-        let mut o_pos: Option<usize> = None;
-        let mut c_pos: Option<usize> = None;
-        let mut v_pos: Option<usize> = None;
-    
-        for (idx, column) in d.history.columns.iter().enumerate() {
-            match column.as_str() {
-                "TRADEDATE" => d_pos = Some(idx),
-                "HIGH"      => h_pos = Some(idx),
-                "LOW"       => l_pos = Some(idx),
-                "OPEN"      => o_pos = Some(idx),
-                "CLOSE"     => c_pos = Some(idx),
-                "VOLUME"    => v_pos = Some(idx),
-                _ => (),
-            }
+    pub fn new() -> Moex {
+        Moex::with_config(MoexConfig::default())
+    }
+
+    pub fn with_base_url(base_url: &str) -> Moex {
+        Moex::with_config(MoexConfig::with_base_url(base_url))
+    }
+
+    pub fn with_config(config: MoexConfig) -> Moex {
+        Moex { base_url: config.base_url.clone(), client: config.client() }
+    }
+
+    /// Uses `client` with its timeout, retry and rate limit settings, headers and credentials for the requests.
+    pub fn with_client(mut self, client: HttpClient) -> Moex {
+        self.client = client;
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Daily bars come from the history endpoint, the other intervals from candles.
+    pub async fn request_data(&self, instrument: &Instrument, interval: TradeInterval, from: NaiveDateTime, till: NaiveDateTime) -> Result<TradeData, MoexError> {
+        let mut trade_data: TradeData =
+            match interval {
+                TradeInterval::Day => self.request_history(instrument, from, till).await?,
+                _ => self.request_candles(instrument, interval, from, till).await?,
+            };
+        if trade_data.is_empty() {
+            return Err(MoexError::NoData(instrument.ticker.clone()));
         }
-    
+        trade_data.set_instrument(instrument.clone());
+        Ok(trade_data)
+    }
+
+    pub(crate) async fn get_json<T: DeserializeOwned>(&self, url: String) -> Result<T, MoexError> {
+        let body: String = self.client.get_text(&url).await?;
+        serde_json::from_str(&body).map_err(|e| MoexError::Decode(e.to_string()))
+    }
+
+    async fn request_page(&self, instrument: &Instrument, from: NaiveDateTime, till: NaiveDateTime, start: usize) -> Result<Value, MoexError> {
+        let url = format!("{}/iss/history/{}/securities/{}.json?from={}&till={}&start={}",
+            self.base_url, instrument.board.path(), percent_encode(&instrument.ticker), from.format("%Y-%m-%d"), till.format("%Y-%m-%d"), start);
+
+        self.get_json(url).await
+    }
+
+    pub async fn request_history(&self, instrument: &Instrument, from: NaiveDateTime, till: NaiveDateTime) -> Result<TradeData, MoexError> {
         let mut trade_data: TradeData = TradeData::new(TradeInterval::Day);
-    
-        if let Some(dpos) = d_pos {
-            if let Some(hpos) = h_pos {
-                if let Some(lpos) = l_pos {
-                    if let Some(opos) = o_pos {
-                        if let Some(cpos) = c_pos {
-                            if let Some(vpos) = v_pos {
-                                for dt in d.history.data {
-                                    trade_data.add_item(
-                                        TradeItem::new(
-                                            get_datetime(&dt[dpos]).unwrap(),
-                                            get_value(&dt[hpos]).unwrap(),
-                                            get_value(&dt[lpos]).unwrap(),
-                                            get_value(&dt[opos]).unwrap(),
-                                            get_value(&dt[cpos]).unwrap(),
-                                            get_value(&dt[vpos]).unwrap(),
-                                        )
-                                    )
-                                }
-                            }
-                        }
-                    }
-                }
+        let mapping: KlineMapping = KlineMapping::iss("history", instrument.board.history_columns(), "%Y-%m-%d");
+
+        // ISS returns the history page by page, the `history.cursor` block tells where the next one starts
+        let mut start: usize = 0;
+        for _ in 0..MAX_PAGES {
+            let d: Value = self.request_page(instrument, from, till, start).await?;
+            let rows: usize = mapping.add_rows(&d, start, &mut trade_data)?;
+
+            let cursor: Option<MoexHistory> = d.get("history.cursor").and_then(|cursor| MoexHistory::deserialize(cursor).ok());
+            match cursor.as_ref().and_then(MoexCursor::from_history).and_then(|c| c.next_start()) {
+                Some(next) if rows > 0 && next > start => start = next,
+                _ => return Ok(trade_data),
             }
         }
 
-        Ok(trade_data)
+        Err(MoexError::TooManyPages(MAX_PAGES))
+    }
+
+    async fn request_candles_page(&self, instrument: &Instrument, interval: TradeInterval, from: NaiveDateTime, till: NaiveDateTime, start: usize) -> Result<Value, MoexError> {
+        let url = format!("{}/iss/{}/securities/{}/candles.json?from={}&till={}&interval={}&start={}",
+            self.base_url, instrument.board.path(), percent_encode(&instrument.ticker), from.format("%Y-%m-%d %H:%M:%S"), till.format("%Y-%m-%d %H:%M:%S"), candle_interval(interval), start);
+
+        self.get_json(url).await
+    }
+
+    pub async fn request_candles(&self, instrument: &Instrument, interval: TradeInterval, from: NaiveDateTime, till: NaiveDateTime) -> Result<TradeData, MoexError> {
+        let mut trade_data: TradeData = TradeData::new(interval);
+        let mapping: KlineMapping = KlineMapping::iss("candles", &CANDLE_COLUMNS, "%Y-%m-%d %H:%M:%S");
+
+        // the candles endpoint has no cursor block, pages are requested until an empty one comes back
+        let mut start: usize = 0;
+        for _ in 0..MAX_PAGES {
+            let d: Value = self.request_candles_page(instrument, interval, from, till, start).await?;
+            let rows: usize = mapping.add_rows(&d, start, &mut trade_data)?;
+            if rows == 0 {
+                return Ok(trade_data);
+            }
+            start += rows;
+        }
+
+        Err(MoexError::TooManyPages(MAX_PAGES))
+    }
+
+    /// Current LAST, OPEN, HIGH, LOW and VOLTODAY of the security, `None` before the first trade of the day.
+    pub async fn request_quote(&self, instrument: &Instrument) -> Result<Option<Quote>, MoexError> {
+        let url = format!("{}/iss/{}/securities/{}.json?iss.only=marketdata&marketdata.columns=SECID,LAST,OPEN,HIGH,LOW,VOLTODAY,SYSTIME",
+            self.base_url, instrument.board.path(), percent_encode(&instrument.ticker));
+
+        let d: MoexMarketDataResponse = self.get_json(url).await?;
+        let table: &MoexHistory = &d.marketdata;
+        let row: &Vec<MoexValue> =
+            match table.data.first() {
+                Some(row) => row,
+                None => return Err(MoexError::NoData(instrument.ticker.clone())),
+            };
+        let value = |column: &'static str| -> Result<Option<f64>, MoexError> {
+            let pos: usize = table.column(column).ok_or_else(|| MoexError::MissingColumn(column.to_string()))?;
+            Ok(row.get(pos).and_then(get_value))
+        };
+
+        let last: f64 =
+            match value("LAST")? {
+                Some(last) => last,
+                None => return Ok(None),
+            };
+        let time_pos: usize = table.column("SYSTIME").ok_or_else(|| MoexError::MissingColumn(String::from("SYSTIME")))?;
+        let time: DateTime<Utc> = row.get(time_pos).and_then(get_datetime).ok_or_else(|| MoexError::BadRow { row: 0, column: String::from("SYSTIME") })?;
+
+        Ok(
+            Some(
+                Quote {
+                    time,
+                    last,
+                    open: value("OPEN")?,
+                    high: value("HIGH")?,
+                    low: value("LOW")?,
+                    volume: value("VOLTODAY")?,
+                }
+            )
+        )
+    }
+}
+
+impl DataSource for Moex {
+    fn fetch<'a>(&'a self, instrument: &'a Instrument, interval: TradeInterval, period: &'a Period<Utc>) -> SourceFuture<'a> {
+        Box::pin(async move {
+            Ok(self.request_data(instrument, interval, calendar::exchange_local(period.begin()), calendar::exchange_local(period.end())).await?)
+        })
+    }
+    fn quote<'a>(&'a self, instrument: &'a Instrument) -> QuoteFuture<'a> {
+        Box::pin(async move {
+            Ok(self.request_quote(instrument).await?)
+        })
     }
 }
 
@@ -123,6 +559,267 @@ fn moex_check() {
            Ok(NaiveDate::from_ymd_opt(2022, 11, 1).unwrap()));
     assert_eq!(NaiveDateTime::parse_from_str("2022-11-01 00:00:00", "%Y-%m-%d %H:%M:%S"),
            Ok(NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 11, 1).unwrap(),NaiveTime::default())));
-    let v: DateTime<Utc> = NaiveDate::parse_from_str("2022-11-01","%Y-%m-%d").map(|d| NaiveDateTime::new(d, NaiveTime::default())).map(|d| DateTime::from_utc(d,Utc)).unwrap();
+    let v: DateTime<Utc> = NaiveDate::parse_from_str("2022-11-01","%Y-%m-%d").map(|d| NaiveDateTime::new(d, NaiveTime::default())).map(|d| DateTime::from_naive_utc_and_offset(d,Utc)).unwrap();
     assert_eq!(v, DateTime::parse_from_rfc2822("Tue, 01 Nov 2022 00:00:00 GMT").unwrap());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart::tradedata::{ GapPolicy, TradeExtras, TradeItem };
+    use crate::stand_in::{ StandIn, query_param };
+
+    fn history_page(start: usize, total: usize, page_size: usize) -> String {
+        let rows: Vec<String> = (start..total.min(start + page_size))
+            .map(|i| {
+                let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap() + chrono::Duration::days(i as i64);
+                format!(r#"["TQBR", "{}", "GAZP", {}, {}.5, {}, {}, {}]"#, date.format("%Y-%m-%d"), 100 + i, 101 + i, 99 + i, 100 + i, 1000 + i)
+            })
+            .collect();
+        format!(
+            r#"{{"history": {{"columns": ["BOARDID", "TRADEDATE", "SECID", "OPEN", "HIGH", "LOW", "CLOSE", "VOLUME"], "data": [{}]}},
+                "history.cursor": {{"columns": ["INDEX", "TOTAL", "PAGESIZE"], "data": [[{}, {}, {}]]}}}}"#,
+            rows.join(","), start, total, page_size
+        )
+    }
+
+    fn gazp() -> Instrument {
+        Instrument::with_board("GAZP", Board::shares())
+    }
+
+    #[test]
+    fn cursor_next_start() {
+        assert_eq!(MoexCursor { index: 0, total: 250, page_size: 100 }.next_start(), Some(100));
+        assert_eq!(MoexCursor { index: 200, total: 250, page_size: 100 }.next_start(), None);
+        assert_eq!(MoexCursor { index: 0, total: 100, page_size: 100 }.next_start(), None);
+        assert_eq!(MoexCursor { index: 0, total: 10, page_size: 0 }.next_start(), None);
+    }
+
+    #[tokio::test]
+    async fn request_data_follows_cursor() {
+        let server = StandIn::serve(|target| {
+            let start: usize = query_param(target, "start").and_then(|s| s.parse().ok()).unwrap_or(0);
+            (200, history_page(start, 250, 100))
+        });
+
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(), NaiveTime::default());
+        let till = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 12, 31).unwrap(), NaiveTime::default());
+        let trade_data = Moex::with_base_url(server.url()).request_data(&gazp(), TradeInterval::Day, from, till).await.unwrap();
+
+        assert_eq!(trade_data.len(), 250);
+        assert_eq!(trade_data.instrument(), Some(&gazp()));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(query_param(&requests[0], "start"), Some("0"));
+        assert_eq!(query_param(&requests[1], "start"), Some("100"));
+        assert_eq!(query_param(&requests[2], "start"), Some("200"));
+        assert!(requests.iter().all(|r| query_param(r, "till") == Some("2022-12-31")));
+        assert_eq!(trade_data.iter_data().last().unwrap().hlocv().c, 349.0);
+    }
+
+    #[tokio::test]
+    async fn request_candles_stops_paging() {
+        // a server ignoring `start` never runs out of rows
+        let server = StandIn::serve(|_| (200, String::from(r#"{"candles": {"columns": ["open", "close", "high", "low", "value", "volume", "begin", "end"],
+            "data": [[100, 101, 102, 99, 1000.0, 1, "2023-03-01 10:00:00", "2023-03-01 10:00:59"]]}}"#)));
+        let mut moex = Moex::with_base_url(server.url());
+        moex.client.share_rate_limit(Rc::new(TokenBucket::new(1e9, 1e9)));
+
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(), NaiveTime::default());
+        let result = moex.request_data(&gazp(), TradeInterval::Minute1, from, from).await;
+        assert_eq!(result.err(), Some(MoexError::TooManyPages(MAX_PAGES)));
+        assert_eq!(server.requests().len(), MAX_PAGES);
+    }
+
+    #[tokio::test]
+    async fn encodes_ticker_in_path() {
+        let server = StandIn::serve(|target| {
+            let start: usize = query_param(target, "start").and_then(|s| s.parse().ok()).unwrap_or(0);
+            (200, history_page(start, 10, 100))
+        });
+
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(), NaiveTime::default());
+        let odd = Instrument::with_board("RU/000 A", Board::shares());
+        Moex::with_base_url(server.url()).request_data(&odd, TradeInterval::Day, from, from).await.unwrap();
+        assert!(server.requests()[0].contains("/securities/RU%2F000%20A.json?"));
+    }
+
+    #[tokio::test]
+    async fn request_candles_pages_until_empty() {
+        let server = StandIn::serve(|target| {
+            let start: usize = query_param(target, "start").and_then(|s| s.parse().ok()).unwrap_or(0);
+            let rows: Vec<String> = (start..700.min(start + 500))
+                .map(|i| format!(r#"[{}, {}, {}, {}, 1000.0, {}, "2023-03-01 {:02}:{:02}:00", "2023-03-01 {:02}:{:02}:59"]"#,
+                    100 + i, 101 + i, 102 + i, 99 + i, 1 + i, 10 + i / 60, i % 60, 10 + i / 60, i % 60))
+                .collect();
+            (200, format!(r#"{{"candles": {{"columns": ["open", "close", "high", "low", "value", "volume", "begin", "end"], "data": [{}]}}}}"#, rows.join(",")))
+        });
+
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(), NaiveTime::default());
+        let till = NaiveDateTime::new(NaiveDate::from_ymd_opt(2023, 3, 2).unwrap(), NaiveTime::default());
+        let trade_data = Moex::with_base_url(server.url()).request_data(&gazp(), TradeInterval::Minute1, from, till).await.unwrap();
+
+        assert_eq!(trade_data.len(), 700);
+        assert_eq!(*trade_data._interval(), TradeInterval::Minute1);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| query_param(r, "interval") == Some("1")));
+        assert!(requests[0].contains("/securities/GAZP/candles.json"));
+
+        // candle times are on the exchange clock, 10:00 in Moscow is 07:00 UTC
+        let first = trade_data.iter_data().next().unwrap();
+        assert_eq!(first._timestamp(), NaiveDateTime::parse_from_str("2023-03-01 07:00:00", "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp());
+        assert_eq!(first.hlocv().h, 102.0);
+    }
+
+    fn stand_in_day(body: &'static str) -> StandIn {
+        StandIn::serve(move |_| (200, body.to_string()))
+    }
+
+    async fn request_day(server: &StandIn) -> Result<TradeData, MoexError> {
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(), NaiveTime::default());
+        let till = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 1, 31).unwrap(), NaiveTime::default());
+        Moex::with_base_url(server.url()).request_data(&gazp(), TradeInterval::Day, from, till).await
+    }
+
+    #[tokio::test]
+    async fn request_data_errors() {
+        let server = StandIn::serve(|_| (404, String::from("not found")));
+        assert_eq!(request_day(&server).await.err(), Some(MoexError::HttpStatus(404)));
+
+        let server = stand_in_day(r#"{"history": "#);
+        assert!(matches!(request_day(&server).await, Err(MoexError::Decode(_))));
+
+        let server = stand_in_day(r#"{"history": {"columns": ["TRADEDATE", "HIGH", "LOW", "OPEN", "CLOSE"], "data": [["2022-01-03", 2, 1, 1, 2]]}}"#);
+        assert_eq!(request_day(&server).await.err(), Some(MoexError::MissingColumn(String::from("VOLUME"))));
+
+        let server = stand_in_day(r#"{"history": {"columns": ["TRADEDATE", "HIGH", "LOW", "OPEN", "CLOSE", "VOLUME"],
+            "data": [["2022-01-03", 2, 1, 1, 2, 10], ["2022-01-04", 2, 1, "-", 2, 10]]}}"#);
+        assert_eq!(request_day(&server).await.err(), Some(MoexError::BadRow { row: 1, column: String::from("OPEN") }));
+
+        let server = stand_in_day(r#"{"history": {"columns": ["TRADEDATE", "HIGH", "LOW", "OPEN", "CLOSE", "VOLUME"], "data": []}}"#);
+        let error = request_day(&server).await.err().unwrap();
+        assert_eq!(error.to_string(), "no data for ticker GAZP");
+    }
+
+    #[tokio::test]
+    async fn request_data_with_gaps() {
+        let server = stand_in_day(r#"{"history": {"columns": ["TRADEDATE", "HIGH", "LOW", "OPEN", "CLOSE", "VOLUME"],
+            "data": [["2022-01-02", null, null, null, null, 0], ["2022-01-03", 2, 1, 1, 1.5, 10],
+                     ["2022-01-04", null, null, null, null, 0], ["2022-01-05", 2, 1, null, 2, null], ["2022-01-06", 3, 2, 2, 3, 10],
+                     ["2022-01-09", null, null, null, null, 0]]}}"#);
+        let trade_data: TradeData = request_day(&server).await.unwrap();
+
+        // the leading row has no previous close and is dropped
+        assert_eq!(trade_data.len(), 5);
+        let gaps: Vec<bool> = trade_data.iter_data().map(|item| item.is_gap()).collect();
+        assert_eq!(gaps, [false, true, false, false, true]);
+        assert_eq!(trade_data.item(1).unwrap().hlocv().c, 1.5);
+        assert_eq!(trade_data.item(1).unwrap().hlocv().v, 0.0);
+        // a row with some prices missing keeps its trades, the open is taken from the close
+        let partial: TradeItem = trade_data.item(2).unwrap();
+        assert!(!partial.is_gap());
+        assert_eq!((partial.hlocv().h, partial.hlocv().l, partial.hlocv().o, partial.hlocv().c), (2.0, 1.0, 2.0, 2.0));
+
+        let mut skipped: TradeData = trade_data.clone();
+        skipped.apply_gap_policy(GapPolicy::Skip);
+        assert_eq!(skipped.len(), 3);
+        let day = |d: u32| calendar::exchange_midnight(NaiveDate::from_ymd_opt(2022, 1, d).unwrap());
+        assert_eq!(*skipped.period(), Period::new(day(3), day(6)));
+        assert!(skipped.validate().is_ok());
+
+        let mut carried: TradeData = trade_data.clone();
+        carried.apply_gap_policy(GapPolicy::CarryForward);
+        assert_eq!(carried.len(), 5);
+        assert!(carried.iter_data().all(|item| !item.is_gap()));
+
+        let mut marked: TradeData = trade_data;
+        marked.apply_gap_policy(GapPolicy::Mark);
+        assert_eq!(marked.iter_data().filter(|item| item.is_gap()).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn request_data_extras() {
+        let server = stand_in_day(r#"{"history": {"columns": ["BOARDID", "TRADEDATE", "SHORTNAME", "SECID", "NUMTRADES", "VALUE", "OPEN", "LOW", "HIGH", "LEGALCLOSEPRICE", "WAPRICE", "CLOSE", "VOLUME"],
+            "data": [["TQBR", "2022-01-03", "ГАЗПРОМ ао", "GAZP", 61234, 15800000000.5, 340.5, 338.1, 347.9, 346.3, 343.6, 346.2, 45987000],
+                     ["TQBR", "2022-01-04", "ГАЗПРОМ ао", "GAZP", 0, 0, null, null, null, 346.3, null, null, 0]]}}"#);
+        let trade_data: TradeData = request_day(&server).await.unwrap();
+
+        assert_eq!(trade_data.item(0).unwrap().extras(), &TradeExtras {
+            waprice: Some(343.6),
+            value: Some(15800000000.5),
+            num_trades: Some(61234),
+            legal_close: Some(346.3),
+        });
+        // the official close is known also for a day without trades
+        assert!(trade_data.item(1).unwrap().is_gap());
+        assert_eq!(trade_data.item(1).unwrap().extras().legal_close, Some(346.3));
+        assert_eq!(trade_data.item(1).unwrap().extras().waprice, None);
+    }
+
+    #[tokio::test]
+    async fn request_with_config() {
+        let server = StandIn::serve(|target| (200, history_page(query_param(target, "start").and_then(|s| s.parse().ok()).unwrap_or(0), 1, 100)));
+        let mut config = MoexConfig::new();
+        config.set_base_url(&format!("{}/proxy/", server.url()));
+        config.add_header("X-Client", "tradecharts");
+        config.set_token("secret");
+        let moex = Moex::with_config(config);
+
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(), NaiveTime::default());
+        moex.request_data(&gazp(), TradeInterval::Day, from, from).await.unwrap();
+
+        assert!(server.requests()[0].starts_with("/proxy/iss/history/engines/stock/markets/shares/boards/TQBR/securities/GAZP.json?"));
+        let headers: Vec<String> = server.headers().iter().map(|h| h.to_lowercase()).collect();
+        assert!(headers.contains(&String::from("x-client: tradecharts")));
+        assert!(headers.contains(&String::from("authorization: bearer secret")));
+
+        let mut config = MoexConfig::with_base_url(server.url());
+        config.set_credentials("user", "pass");
+        Moex::with_config(config).request_data(&gazp(), TradeInterval::Day, from, from).await.unwrap();
+        assert!(server.headers().contains(&String::from("authorization: Basic dXNlcjpwYXNz")));
+    }
+
+    #[tokio::test]
+    async fn request_data_on_board() {
+        let server = StandIn::serve(|_| (200, String::from(r#"{"history": {"columns": ["BOARDID", "TRADEDATE", "SECID", "OPEN", "LOW", "HIGH", "CLOSE", "NUMTRADES", "VOLRUR"],
+            "data": [["CETS", "2023-03-01", "USD000UTSTOM", 75.1, 74.9, 75.8, 75.5, 1200, 9000000]]}}"#)));
+
+        let from = NaiveDateTime::new(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(), NaiveTime::default());
+        let till = NaiveDateTime::new(NaiveDate::from_ymd_opt(2023, 3, 2).unwrap(), NaiveTime::default());
+        let trade_data = Moex::with_base_url(server.url()).request_data(&Instrument::with_board("USD000UTSTOM", Board::currency()), TradeInterval::Day, from, till).await.unwrap();
+
+        assert_eq!(trade_data.len(), 1);
+        assert_eq!(trade_data.iter_data().next().unwrap().hlocv().v, 9000000.0);
+        assert!(server.requests()[0].starts_with("/iss/history/engines/currency/markets/selt/boards/CETS/securities/USD000UTSTOM.json?"));
+
+        let server = StandIn::serve(|_| (200, String::from(r#"{"candles": {"columns": ["open", "close", "high", "low", "value", "volume", "begin", "end"], "data": []}}"#)));
+        let _ = Moex::with_base_url(server.url()).request_data(&Instrument::with_board("SiH3", Board::futures()), TradeInterval::Hour, from, till).await;
+        assert!(server.requests()[0].starts_with("/iss/engines/futures/markets/forts/boards/RFUD/securities/SiH3/candles.json?"));
+    }
+
+    #[tokio::test]
+    async fn moex_as_data_source() {
+        let server = StandIn::serve(|target| {
+            let start: usize = query_param(target, "start").and_then(|s| s.parse().ok()).unwrap_or(0);
+            (200, history_page(start, 30, 100))
+        });
+
+        let source: Box<dyn DataSource> = Box::new(Moex::with_base_url(server.url()));
+        let period: Period<Utc> = Period::new(
+            DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z").unwrap().with_timezone(&Utc),
+            DateTime::parse_from_rfc3339("2022-02-01T00:00:00Z").unwrap().with_timezone(&Utc),
+        );
+        let trade_data = source.fetch(&gazp(), TradeInterval::Day, &period).await.unwrap();
+        assert_eq!(trade_data.len(), 30);
+        assert_eq!(query_param(&server.requests()[0], "from"), Some("2022-01-01"));
+
+        let server = StandIn::serve(|_| (500, String::new()));
+        let mut client = HttpClient::new();
+        client.set_retries(1, chrono::Duration::milliseconds(1));
+        let source: Box<dyn DataSource> = Box::new(Moex::with_base_url(server.url()).with_client(client));
+        let error = source.fetch(&gazp(), TradeInterval::Day, &period).await.err().unwrap();
+        assert_eq!(error.to_string(), "ISS responded with HTTP status 500");
+        assert_eq!(server.requests().len(), 2);
+    }
+}
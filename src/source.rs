@@ -0,0 +1,138 @@
+use std::{ error::Error, fmt, future::Future, pin::Pin };
+use chrono::{ DateTime, Utc };
+use js_sys::{ Array, Function, Promise, Reflect };
+use wasm_bindgen::{ JsCast, JsValue };
+use wasm_bindgen_futures::JsFuture;
+use crate::instrument::Instrument;
+use crate::chart::{
+    Period, TradeInterval,
+    tradedata::{
+        TradeItem, TradeData,
+    }
+};
+
+pub type SourceResult = Result<TradeData, Box<dyn Error>>;
+pub type SourceFuture<'a> = Pin<Box<dyn Future<Output = SourceResult> + 'a>>;
+pub type QuoteFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<Quote>, Box<dyn Error>>> + 'a>>;
+
+/// Current trading state of an instrument: the last price and the aggregates of the day so far.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Quote {
+    pub time: DateTime<Utc>,
+    pub last: f64,
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub volume: Option<f64>,
+}
+
+/// Anything able to provide the bars of an instrument for an interval and period.
+pub trait DataSource {
+    fn fetch<'a>(&'a self, instrument: &'a Instrument, interval: TradeInterval, period: &'a Period<Utc>) -> SourceFuture<'a>;
+
+    /// Last price of the instrument, sources without live data have none.
+    fn quote<'a>(&'a self, _instrument: &'a Instrument) -> QuoteFuture<'a> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+#[derive(Debug)]
+pub enum CallbackError {
+    Failed(String),
+    /// The callback gave no bars for the period.
+    NoData(String),
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Failed(message)   => write!(f, "data source callback failed: {}", message),
+            Self::NoData(ticker)    => write!(f, "no data for ticker {}", ticker),
+        }
+    }
+}
+
+impl Error for CallbackError {}
+
+impl From<JsValue> for CallbackError {
+    fn from(e: JsValue) -> CallbackError {
+        CallbackError::Failed(e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+    }
+}
+
+/// Source backed by a JS function `(ticker, interval, fromMs, tillMs)` returning
+/// (a promise of) an array of bars. A bar is either `[timeMs, open, high, low, close, volume]`
+/// or an object with the `time`, `open`, `high`, `low`, `close` and `volume` fields.
+/// `interval` is a value of the exported `TradeInterval` enum, e.g. `TradeInterval.Day`.
+/// An empty array means the period has no bars.
+pub struct JsSource {
+    callback: Function,
+}
+
+impl JsSource {
+    pub fn new(callback: Function) -> JsSource {
+        JsSource { callback }
+    }
+
+    async fn call(&self, instrument: &Instrument, interval: TradeInterval, period: &Period<Utc>) -> Result<TradeData, CallbackError> {
+        let args: Array = Array::of4(
+            &JsValue::from_str(&instrument.ticker),
+            &JsValue::from(interval),
+            &JsValue::from(period.begin().timestamp_millis() as f64),
+            &JsValue::from(period.end().timestamp_millis() as f64),
+        );
+        let mut result: JsValue = self.callback.apply(&JsValue::NULL, &args)?;
+        if result.has_type::<Promise>() {
+            result = JsFuture::from(result.unchecked_into::<Promise>()).await?;
+        }
+        if !Array::is_array(&result) {
+            return Err(CallbackError::Failed(String::from("callback result is not an array of bars")));
+        }
+        let bars: Array = Array::from(&result);
+        if bars.length() == 0 {
+            return Err(CallbackError::NoData(instrument.ticker.clone()));
+        }
+
+        let mut trade_data: TradeData = TradeData::new(interval);
+        trade_data.set_instrument(instrument.clone());
+        for bar in bars.iter() {
+            trade_data.add_item(bar_from_js(&bar)?);
+        }
+        Ok(trade_data)
+    }
+}
+
+fn bar_from_js(bar: &JsValue) -> Result<TradeItem, CallbackError> {
+    let field = |name: &str, index: u32| -> Result<f64, CallbackError> {
+        let value: JsValue =
+            if Array::is_array(bar) {
+                Array::from(bar).get(index)
+            } else {
+                Reflect::get(bar, &JsValue::from_str(name))?
+            };
+        value.as_f64().ok_or_else(|| CallbackError::Failed(format!("bar has no numeric {}", name)))
+    };
+
+    let date: DateTime<Utc> = DateTime::from_timestamp_millis(field("time", 0)? as i64)
+        .ok_or_else(|| CallbackError::Failed(String::from("bar time is out of range")))?;
+    Ok(
+        TradeItem::new(
+            date,
+            field("high", 2)?,
+            field("low", 3)?,
+            field("open", 1)?,
+            field("close", 4)?,
+            field("volume", 5)?,
+        )
+    )
+}
+
+impl DataSource for JsSource {
+    fn fetch<'a>(&'a self, instrument: &'a Instrument, interval: TradeInterval, period: &'a Period<Utc>) -> SourceFuture<'a> {
+        Box::pin(async move {
+            Ok(self.call(instrument, interval, period).await?)
+        })
+    }
+}
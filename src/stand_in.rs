@@ -0,0 +1,82 @@
+use std::io::{ BufRead, BufReader, Write };
+use std::net::{ TcpListener, TcpStream };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+
+/// Minimal local HTTP server standing in for ISS in tests.
+/// Every request is answered by the handler with a status code and a body.
+pub struct StandIn {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    headers: Arc<Mutex<Vec<String>>>,
+}
+
+impl StandIn {
+    pub fn serve<F>(handler: F) -> StandIn
+    where
+        F: Fn(&str) -> (u16, String) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let headers: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let (log, header_log) = (requests.clone(), headers.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                Self::answer(stream, &handler, &log, &header_log);
+            }
+        });
+
+        StandIn { url, requests, headers }
+    }
+
+    fn answer<F>(mut stream: TcpStream, handler: &F, log: &Mutex<Vec<String>>, header_log: &Mutex<Vec<String>>)
+    where
+        F: Fn(&str) -> (u16, String),
+    {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        // skip the headers, requests in tests have no body
+        let mut header = String::new();
+        while reader.read_line(&mut header).map(|n| n > 2).unwrap_or(false) {
+            header_log.lock().unwrap().push(header.trim_end().to_string());
+            header.clear();
+        }
+
+        let target: String = request_line.split_whitespace().nth(1).unwrap_or("/").to_string();
+        log.lock().unwrap().push(target.clone());
+
+        let (status, body) = handler(&target);
+        let response = format!(
+            "HTTP/1.1 {} Stand-In\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, body.len(), body
+        );
+        let _ = stream.write_all(response.as_bytes());
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Header lines of all requests so far, like `accept: */*`.
+    pub fn headers(&self) -> Vec<String> {
+        self.headers.lock().unwrap().clone()
+    }
+}
+
+/// Value of the query parameter `name` in a request target like `/path?a=1&b=2`.
+pub fn query_param<'a>(target: &'a str, name: &str) -> Option<&'a str> {
+    target.split_once('?')?.1
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
@@ -0,0 +1,79 @@
+//! Runs the command line tool against a local stand-in for ISS.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::{ fs, path::PathBuf, process::{ Command, Output } };
+
+#[allow(dead_code)]
+#[path = "../src/stand_in.rs"]
+mod stand_in;
+
+use stand_in::{ StandIn, query_param };
+
+const HISTORY: &str = r#"{"history": {"columns": ["BOARDID", "TRADEDATE", "SECID", "OPEN", "LOW", "HIGH", "CLOSE", "VOLUME"],
+    "data": [["TQBR", "2023-03-01", "GAZP", 160.5, 159.8, 164.0, 163.2, 1000],
+             ["TQBR", "2023-03-02", "GAZP", null, null, null, null, 0],
+             ["TQBR", "2023-03-03", "GAZP", 163.0, 161.1, 165.5, 165.0, 3000]]}}"#;
+
+fn tradecharts(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tradecharts")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir: PathBuf = std::env::temp_dir().join(format!("tradecharts-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn fetch_convert_and_stats() {
+    let server = StandIn::serve(|_| (200, String::from(HISTORY)));
+    let dir: PathBuf = temp_dir("fetch");
+    let csv: String = dir.join("gazp.csv").display().to_string();
+    let json: String = dir.join("gazp.json").display().to_string();
+    let cache: String = dir.join("cache").display().to_string();
+
+    let out: String = stdout(&tradecharts(&["fetch", "GAZP", "--iss", server.url(), "--from", "2023-03-01", "--till", "2023-03-03",
+        "--out", &csv, "--cache", &cache]));
+    assert!(out.contains("instrument: stock/shares/TQBR/GAZP"));
+    assert!(out.contains("bars: 3 (1 without trades)"));
+    assert!(out.contains("period: 2023-03-01 .. 2023-03-03 MSK"));
+    assert!(out.contains("range: 159.80 .. 165.50"));
+    let requests: Vec<String> = server.requests();
+    assert!(requests[0].starts_with("/iss/history/engines/stock/markets/shares/boards/TQBR/securities/GAZP.json?"));
+    assert_eq!(query_param(&requests[0], "from"), Some("2023-03-01"));
+    assert_eq!(query_param(&requests[0], "till"), Some("2023-03-03"));
+    assert!(fs::read_to_string(&csv).unwrap().starts_with("<DATE>,<TIME>,<OPEN>,<HIGH>,<LOW>,<CLOSE>,<VOL>\n20230301,000000,160.5,164,159.8,163.2,1000\n"));
+
+    // the cache directory holds a chunk file, readable like any JSON
+    let chunk: PathBuf = fs::read_dir(&cache).unwrap().next().unwrap().unwrap().path();
+    let out: String = stdout(&tradecharts(&["stats", chunk.to_str().unwrap()]));
+    assert!(out.contains("bars: 3 (1 without trades)"));
+
+    assert_eq!(stdout(&tradecharts(&["convert", &csv, &json])).trim(), format!("3 bars written to {}", json));
+    let out: String = stdout(&tradecharts(&["stats", &json]));
+    assert!(out.contains("interval: Day"));
+    assert!(out.contains("open: 160.50 close: 165.00 change: +2.80%"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn reports_errors() {
+    let server = StandIn::serve(|_| (404, String::from("not found")));
+    let output: Output = tradecharts(&["fetch", "GAZP", "--iss", server.url(), "--from", "2023-03-01"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ISS responded with HTTP status 404"));
+
+    let output: Output = tradecharts(&["stats", "gazp.csv", "--interval", "fortnight"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown interval fortnight"));
+
+    assert!(stdout(&tradecharts(&[])).starts_with("usage:"));
+}
@@ -1,10 +1,58 @@
 import * as wasm from 'tradecharts';
 var wglchart;
+var catalog;
+// securities of the default board, the list shown without a search query
+var board = null;
+// ISS access is set up by the page parameters, e.g. `?iss=https://proxy.example.com&token=...`
+function moexConfig() {
+  const params = new URLSearchParams(window.location.search);
+  const config = wasm.MoexConfig.new();
+  if (params.get("iss")) config.set_base_url(params.get("iss"));
+  if (params.get("token")) config.set_token(params.get("token"));
+  return config;
+}
+// the chart is borrowed mutably by every async call, so the calls run one after another
+var pending = Promise.resolve();
 
 var app = new Vue({
     el: '#app',
     data: {
         activeticker: "GAZP",
+        activeissuer: {ticker: "GAZP"},
+        price: "",
+        tooltip: "",
+        wap: false,
+        adjusted: false,
+        turnover: false,
+        gappolicy: wasm.GapPolicy.Skip,
+        gappolicies: [
+          {policy: wasm.GapPolicy.Skip, name: "Без пропусков" },
+          {policy: wasm.GapPolicy.CarryForward, name: "Цена закрытия" },
+          {policy: wasm.GapPolicy.Mark, name: "Пропуски" },
+        ],
+        // bars put together from the loaded ones without a new request
+        resampling: "",
+        resamplings: [
+          {key: "", name: "Как загружено" },
+          {key: "days3", name: "3 дня" },
+          {key: "week", name: "Неделя" },
+          {key: "month", name: "Месяц" },
+        ],
+        query: "",
+        error: "",
+        live: false,
+        // [x, text] pairs under the chart, dates and times in Moscow
+        timelabels: [],
+        missing: [],
+        activeinterval: wasm.TradeInterval.Day,
+        intervals: [
+          {interval: wasm.TradeInterval.Minute1, name: "1м" },
+          {interval: wasm.TradeInterval.Minute10, name: "10м" },
+          {interval: wasm.TradeInterval.Hour, name: "Час" },
+          {interval: wasm.TradeInterval.Day, name: "День" },
+          {interval: wasm.TradeInterval.Week, name: "Неделя" },
+          {interval: wasm.TradeInterval.Month, name: "Месяц" },
+        ],
         issuers: [
           {ticker: "GAZP", name: "Газпром" },
           {ticker: "NVTK", name: "Новатэк" },
@@ -41,10 +89,24 @@ var app = new Vue({
       // console.log(this.tickers[this.counter % this.tickers.length]);
       this.adjustResizing();
 
-      wglchart = wasm.TradeChart.new();
-      wglchart.display(this.activeticker);
+      // the chart and the catalog share the rate limit of one configuration
+      const config = moexConfig();
+      wglchart = wasm.TradeChart.with_config(config.share());
+      catalog = wasm.Catalog.with_config(config);
+      catalog.list("stock", "shares", "TQBR")
+        .then(list => {
+          board = list.map(s => ({ticker: s.secid, name: s.short_name, board: ["stock", "shares", s.board_id], decimals: s.decimals, tick: s.min_step}));
+          if (!this.query) this.issuers = board;
+        })
+        .catch(e => console.log(e));
+      // another vendor is set up by a kline mapping file, e.g. `?klines=vendors/binance.json`
+      const klines = new URLSearchParams(window.location.search).get("klines");
+      if (klines) this.load(() => fetch(klines).then(r => r.text()).then(mapping => wglchart.set_json_source(mapping)));
+      this.load(() => wglchart.enable_cache(true).catch(e => console.log(e)));
+      this.load(() => wglchart.display(this.activeticker));
 
       window.addEventListener('resize', this.onWindowResize);
+      setInterval(() => { if (this.live) this.load(() => wglchart.poll_live()); }, 1000);
 
       // var canvas = document.getElementById('axe');
       // var ctx = canvas.getContext('2d');
@@ -65,14 +127,121 @@ var app = new Vue({
       onWindowResize (e) {
         this.adjustResizing();
         wglchart.draw();
+        this.updateAxis();
       },
-      showChart (ticker) {
-        this.activeticker = ticker;
+      instrument (issuer) {
+        const [engine, market, id] = issuer.board || ["stock", "shares", "TQBR"];
+        const instrument = new wasm.Instrument(issuer.ticker, engine, market, id);
+        if (issuer.decimals !== undefined) {
+          instrument.decimals = issuer.decimals;
+          instrument.tick_size = issuer.tick;
+        }
+        return instrument;
+      },
+      showChart (issuer) {
+        this.activeticker = issuer.ticker;
+        this.activeissuer = issuer;
         // console.log(ticker);
-        wglchart.display(this.activeticker);
+        this.load(() => wglchart.display_instrument(this.instrument(issuer), this.activeinterval));
+      },
+      showGaps () {
+        const policy = this.gappolicy;
+        this.load(() => wglchart.set_gap_policy(policy));
+        this.showChart(this.activeissuer);
+      },
+      showPrice (e) {
+        // the chart is busy while a load is running
+        try {
+          this.price = wglchart.price_at(e.offsetY);
+          this.tooltip = wglchart.tooltip_at(e.offsetX) || "";
+        } catch (err) {}
+      },
+      toggleWap () {
+        this.wap = !this.wap;
+        const wap = this.wap;
+        this.load(() => wglchart.show_wap(wap));
+      },
+      toggleAdjusted () {
+        this.adjusted = !this.adjusted;
+        const adjusted = this.adjusted;
+        this.load(() => wglchart.set_adjusted(adjusted));
+      },
+      loadActions (e) {
+        const file = e.target.files[0];
+        if (!file) return;
+        file.text().then(text => this.load(() => wglchart.load_actions_json(text)));
+      },
+      toggleTurnover () {
+        this.turnover = !this.turnover;
+        const turnover = this.turnover;
+        this.load(() => wglchart.show_turnover(turnover));
+      },
+      search () {
+        const query = this.query.trim();
+        if (query.length < 3) {
+          if (board) this.issuers = board;
+          return;
+        }
+        catalog.search(query)
+          .then(found => {
+            if (query != this.query.trim()) return;
+            this.issuers = found
+              .filter(s => s.is_traded && s.primary_board)
+              .map(s => ({ticker: s.secid, name: s.short_name, board: [s.engine, s.market, s.primary_board]}));
+          })
+          .catch(e => { this.error = e; });
+      },
+      resample () {
+        const resampling = this.resampling;
+        this.load(() => {
+          if (resampling == "days3") return wglchart.resample_days(3);
+          if (resampling == "week") return wglchart.resample(wasm.TradeInterval.Week);
+          if (resampling == "month") return wglchart.resample(wasm.TradeInterval.Month);
+          return wglchart.resample(wglchart.loaded_interval());
+        });
+      },
+      showInterval (interval) {
+        this.activeinterval = interval;
+        this.resampling = "";
+        this.load(() => wglchart.display_instrument(this.instrument(this.activeissuer), this.activeinterval));
+      },
+      toggleLive () {
+        this.live = !this.live;
+        const live = this.live;
+        this.load(() => live ? wglchart.start_live(10000) : wglchart.stop_live());
+      },
+      load (call) {
+        this.error = "";
+        pending = pending.then(call).then(() => this.updateAxis()).catch(e => { this.error = e; });
+      },
+      updateAxis () {
+        this.timelabels = wglchart.time_labels(8);
+        this.missing = wglchart.missing_sessions();
+      },
+      loadFile (e) {
+        const file = e.target.files[0];
+        if (!file) return;
+        file.arrayBuffer().then(buffer => {
+          this.error = "";
+          try {
+            wglchart.load_csv_bytes(new Uint8Array(buffer), wasm.CsvFormat.new(), this.activeinterval);
+            this.updateAxis();
+          } catch (err) {
+            this.error = err;
+          }
+        });
+      },
+      exportVisible () {
+        const text = wglchart.export_csv(wasm.CsvFormat.new(), true);
+        const link = document.createElement("a");
+        link.href = URL.createObjectURL(new Blob([text], {type: "text/csv"}));
+        link.download = this.activeticker + ".csv";
+        link.click();
+        URL.revokeObjectURL(link.href);
       },
       shiftChart (b) {
         wglchart.shift(b ? 1.0 : -1.0);
+        this.updateAxis();
       },
     }
   })